@@ -0,0 +1,136 @@
+use crate::data_model::{TimeRange, TimeSeries, TimeSeriesEntry};
+use crate::errors::HandlingError;
+use crate::interpolation::{interpolate, Interpolation};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+/// Keeps a single request from building an arbitrarily large table.
+pub const MAX_GRID_POINTS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grid {
+    /// Time points every given number of seconds, at multiples of the interval since the epoch.
+    Regular(i64),
+    /// The time points of all series.
+    Union,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fill {
+    /// Only entries exactly at a grid time point, null elsewhere.
+    Exact,
+    Interpolated(Interpolation),
+}
+
+/// Builds the time axis shared by the series. The series may contain entries outside the range,
+/// which are only used for interpolation.
+pub fn grid_time_points(
+    grid: Grid,
+    time_series: &[TimeSeries],
+    range: &TimeRange,
+) -> Result<Vec<DateTime<Utc>>, HandlingError> {
+    let in_range = time_series
+        .iter()
+        .flat_map(|series| series.time_points.iter())
+        .filter(|time_point| range.contains(time_point));
+
+    let time_points = match grid {
+        Grid::Union => {
+            let mut time_points: Vec<DateTime<Utc>> = in_range.copied().collect();
+            time_points.sort();
+            time_points.dedup();
+            time_points
+        }
+        Grid::Regular(interval) => {
+            // Open ends of the range are closed by the data itself.
+            let start = match range.start.or_else(|| in_range.clone().min().copied()) {
+                Some(start) => start,
+                None => return Ok(vec![]),
+            };
+            let last = range
+                .end
+                .or_else(|| in_range.max().copied())
+                .unwrap_or(start);
+            let bounded = TimeRange::new(
+                Some(start),
+                Some(last),
+                range.end.is_none() || range.end_inclusive,
+            )?;
+
+            // The first multiple of the interval at or after the start.
+            let mut seconds = start.timestamp() - start.timestamp().rem_euclid(interval);
+            if Utc.timestamp_opt(seconds, 0).single() < Some(start) {
+                seconds += interval;
+            }
+
+            let mut time_points: Vec<DateTime<Utc>> = vec![];
+            while let Some(time_point) = Utc.timestamp_opt(seconds, 0).single() {
+                if !bounded.contains(&time_point) {
+                    break;
+                }
+                if time_points.len() == MAX_GRID_POINTS {
+                    return Err(too_many_points());
+                }
+                time_points.push(time_point);
+                seconds = match seconds.checked_add(interval) {
+                    Some(seconds) => seconds,
+                    None => break,
+                };
+            }
+            time_points
+        }
+    };
+
+    if time_points.len() > MAX_GRID_POINTS {
+        return Err(too_many_points());
+    }
+    Ok(time_points)
+}
+
+fn too_many_points() -> HandlingError {
+    HandlingError::Validation(format!(
+        "The grid must not have more than {} time points",
+        MAX_GRID_POINTS
+    ))
+}
+
+/// Samples the series at the grid time points. Both must be in chronological order.
+pub fn values_on_grid(
+    time_series: &TimeSeries,
+    grid: &[DateTime<Utc>],
+    fill: Fill,
+    max_gap: Option<Duration>,
+) -> Vec<Option<f64>> {
+    let entry = |index: usize| TimeSeriesEntry {
+        time_point: time_series.time_points[index],
+        value: time_series.values[index],
+    };
+
+    grid.iter()
+        .map(|time_point| {
+            // The index of the first entry at or after the time point.
+            let index = time_series
+                .time_points
+                .partition_point(|entry_time_point| entry_time_point < time_point);
+            let exact = time_series.time_points.get(index) == Some(time_point);
+
+            match fill {
+                Fill::Exact => exact.then(|| time_series.values[index]),
+                Fill::Interpolated(interpolation) => {
+                    let next = (index < time_series.time_points.len()).then(|| entry(index));
+                    let previous = if exact {
+                        next.clone()
+                    } else {
+                        index.checked_sub(1).map(entry)
+                    };
+                    interpolate(
+                        time_point,
+                        previous.as_ref(),
+                        next.as_ref(),
+                        interpolation,
+                        max_gap,
+                    )
+                }
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,145 @@
+use crate::dao::{ConflictPolicy, Dao};
+use crate::data_model::{Page, Plot, TimeRange, TimeSeries, TimeSeriesEntry};
+use crate::errors::HandlingError;
+use crate::migrations::latest_version;
+use chrono::{Duration, TimeZone, Utc};
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+const SERIES: usize = 10;
+const BATCH_SIZE: usize = 1_000;
+const READS: usize = 1_000;
+const ENTRIES_PER_READ: i64 = 1_000;
+const LOOKUPS: usize = 100_000;
+
+// The setups to compare: the schema version of the entry table layout and whether statements are
+// cached. The first one is the state before the clustered table and the statement cache.
+const SETUPS: &[(i64, bool, &str)] = &[
+    (2, false, "rowid table, statements prepared per call"),
+    (3, false, "clustered table, statements prepared per call"),
+    (3, true, "clustered table, cached statements"),
+];
+
+/// Measures the throughput of appending entries, of reading time ranges and of looking up the
+/// entries next to a time point, for each layout of the entry table with and without the statement
+/// cache. The lookups are small queries, which show the cost of preparing statements the most.
+/// The entries are appended interleaved across several series at one entry per second and series,
+/// as live data arrives.
+pub fn run(entries: usize) -> Result<(), HandlingError> {
+    for (index, (version, cached, description)) in SETUPS.iter().enumerate() {
+        if *version > latest_version() {
+            continue;
+        }
+        let path = std::env::temp_dir().join(format!(
+            "rust-json-bench-{}-{}.db",
+            std::process::id(),
+            index
+        ));
+        let result = run_setup(&path, *version, *cached, entries);
+        remove_database(&path);
+        let (write_rate, read_rate, lookup_rate) = result?;
+        println!(
+            "Schema version {}, {}: {:.0} entries/s written, {:.0} entries/s read, {:.0} lookups/s",
+            version, description, write_rate, read_rate, lookup_rate
+        );
+    }
+    Ok(())
+}
+
+fn run_setup(
+    path: &Path,
+    version: i64,
+    cached: bool,
+    entries: usize,
+) -> Result<(f64, f64, f64), HandlingError> {
+    let mut dao = Dao::open_at_version(path, version)?;
+    if !cached {
+        // Without room in the cache every statement is prepared anew, as before the cache.
+        dao.set_statement_cache_capacity(0);
+    }
+    let plot = dao.add_plot(&Plot {
+        id: 0,
+        name: "bench".to_string(),
+        description: "Benchmark".to_string(),
+        time_series: (0..SERIES)
+            .map(|index| TimeSeries {
+                id: 0,
+                plot_id: 0,
+                name: format!("series {}", index),
+                unit: "".to_string(),
+                time_points: vec![],
+                values: vec![],
+            })
+            .collect(),
+    })?;
+
+    let origin = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+    let per_series = (entries / SERIES) as i64;
+
+    let start = Instant::now();
+    let mut batch: Vec<(i64, TimeSeriesEntry)> = Vec::with_capacity(BATCH_SIZE);
+    for second in 0..per_series {
+        for series in plot.time_series.iter() {
+            batch.push((
+                series.id,
+                TimeSeriesEntry {
+                    time_point: origin + Duration::seconds(second),
+                    value: second as f64,
+                },
+            ));
+        }
+        if batch.len() >= BATCH_SIZE {
+            dao.append_entries(&batch, ConflictPolicy::Reject)?;
+            batch.clear();
+        }
+    }
+    dao.append_entries(&batch, ConflictPolicy::Reject)?;
+    let written = per_series as f64 * SERIES as f64;
+    let write_rate = written / start.elapsed().as_secs_f64();
+
+    // A fixed sequence of pseudo-random windows keeps the runs comparable.
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next_random = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let mut read: usize = 0;
+    let start = Instant::now();
+    for _ in 0..READS {
+        let state = next_random();
+        let series = &plot.time_series[state as usize % SERIES];
+        let first = (state >> 8) as i64 % (per_series - ENTRIES_PER_READ).max(1);
+
+        let mut time_series = dao.get_time_series(series.id)?;
+        let range = TimeRange::new(
+            Some(origin + Duration::seconds(first)),
+            Some(origin + Duration::seconds(first + ENTRIES_PER_READ)),
+            false,
+        )?;
+        dao.get_entries_for_time_series(&mut time_series, &range, &Page::default())?;
+        read += time_series.values.len();
+    }
+    let read_rate = read as f64 / start.elapsed().as_secs_f64();
+
+    let start = Instant::now();
+    for _ in 0..LOOKUPS {
+        let state = next_random();
+        let series = &plot.time_series[state as usize % SERIES];
+        let second = (state >> 8) as i64 % per_series.max(1);
+        dao.get_neighbouring_entries(series.id, &(origin + Duration::seconds(second)))?;
+    }
+    let lookup_rate = LOOKUPS as f64 / start.elapsed().as_secs_f64();
+
+    Ok((write_rate, read_rate, lookup_rate))
+}
+
+fn remove_database(path: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        let _ = fs::remove_file(file);
+    }
+}
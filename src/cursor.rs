@@ -0,0 +1,85 @@
+use crate::errors::HandlingError;
+
+/// The opaque continuation token handed to clients when a result has been cut off by a "Limit".
+/// It records what was queried (kind and scope, e.g. the time series id) and the position of the
+/// last row sent, so that the next page starts right after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub kind: String,
+    pub scope: i64,
+    pub position: String,
+}
+
+impl Cursor {
+    pub fn new(kind: &str, scope: i64, position: String) -> Self {
+        Self {
+            kind: kind.to_string(),
+            scope,
+            position,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{}:{}:{}", self.kind, self.scope, self.position)
+            .bytes()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Decodes a cursor and checks that it belongs to the same kind of query and scope.
+    pub fn decode(cursor: &str, kind: &str, scope: i64) -> Result<Self, HandlingError> {
+        let invalid = || HandlingError::Validation("Invalid Cursor".to_string());
+
+        if !cursor.len().is_multiple_of(2) || !cursor.is_ascii() {
+            return Err(invalid());
+        }
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&cursor[index..index + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        let decoded = String::from_utf8(bytes).map_err(|_| invalid())?;
+
+        let mut parts = decoded.splitn(3, ':');
+        let decoded_kind = parts.next().ok_or_else(invalid)?;
+        let decoded_scope = parts.next().and_then(|scope| scope.parse::<i64>().ok());
+        let position = parts.next().ok_or_else(invalid)?;
+
+        if decoded_kind != kind || decoded_scope != Some(scope) {
+            return Err(invalid());
+        }
+        Ok(Self::new(kind, scope, position.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_reverses_encode() {
+        let cursor = Cursor::new(
+            "entries",
+            7,
+            "2020-09-13T12:26:40.000000001+00:00".to_string(),
+        );
+        assert_eq!(
+            Cursor::decode(&cursor.encode(), "entries", 7).unwrap(),
+            cursor
+        );
+    }
+
+    #[test]
+    fn decode_rejects_other_queries() {
+        let encoded = Cursor::new("entries", 7, "position".to_string()).encode();
+        assert!(Cursor::decode(&encoded, "entries", 8).is_err());
+        assert!(Cursor::decode(&encoded, "plots/Name/asc", 7).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_malformed_cursors() {
+        for cursor in ["", "abc", "zz", "ü0", "656e7472696573"] {
+            assert!(Cursor::decode(cursor, "entries", 7).is_err(), "{}", cursor);
+        }
+    }
+}
@@ -1,127 +1,738 @@
-use crate::data_model::{time_point_to_string, Plot, TimeSeries, TimeSeriesEntry};
+use crate::data_model::{
+    time_point_from_nanos, time_point_to_nanos, time_point_to_string, Bucket, DeletedRows, Page,
+    Plot, PlotFilter, PlotOrder, PlotRetention, PlotSortKey, PlotSummary, SeriesRetention,
+    Snapshot, Statistics, TextMatch, TimeRange, TimeSeries, TimeSeriesEntry,
+};
 use crate::errors::HandlingError;
-use chrono::{DateTime, Utc};
-use rusqlite::{params, Connection, Error, Result};
+use crate::migrations::{latest_version, migrate, migrate_to};
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::backup::{Backup, Progress, StepResult};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{
+    ffi, params, Connection, DatabaseName, Error, OpenFlags, OptionalExtension, Result, Transaction,
+};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
 
-pub struct Dao {
-    conn: Connection,
+/// What to do with an entry whose time series already has an entry at the same time point.
+#[derive(Debug, Clone, Copy)]
+pub enum ConflictPolicy {
+    /// Fail and roll back the whole batch.
+    Reject,
+    /// Keep the stored entry and drop the new one.
+    Skip,
+    /// Replace the value of the stored entry.
+    Overwrite,
 }
 
-impl Dao {
-    fn set_up(&self) -> Result<(), Error> {
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS plot (
-            id INTEGER PRIMARY KEY,
-            name TEXT NOT NULL,
-            description TEXT NOT NULL
-        )",
-            (), // empty list of parameters.
-        )?;
+impl ConflictPolicy {
+    fn insert_statement(&self) -> &'static str {
+        match self {
+            ConflictPolicy::Reject => {
+                "INSERT INTO time_series_entry (time_series_id, date, value) VALUES (?1, ?2, ?3)"
+            }
+            ConflictPolicy::Skip => {
+                "INSERT OR IGNORE INTO time_series_entry (time_series_id, date, value) VALUES (?1, ?2, ?3)"
+            }
+            ConflictPolicy::Overwrite => {
+                "INSERT INTO time_series_entry (time_series_id, date, value) VALUES (?1, ?2, ?3)
+                ON CONFLICT (time_series_id, date) DO UPDATE SET value = excluded.value"
+            }
+        }
+    }
+}
 
-        self.conn.execute(
-            "CREATE UNIQUE INDEX IF NOT EXISTS index_plot_name
-            ON plot ( name )",
-            (), // empty list of parameters.
-        )?;
+// Entries are unique by their primary key, names by unique indices.
+fn is_unique_violation(error: &Error) -> bool {
+    matches!(error, Error::SqliteFailure(err, _)
+        if err.extended_code == ffi::SQLITE_CONSTRAINT_UNIQUE
+        || err.extended_code == ffi::SQLITE_CONSTRAINT_PRIMARYKEY)
+}
 
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS time_series (
-            id INTEGER PRIMARY KEY,
-            plot_id INTEGER,
-            name TEXT NOT NULL,
-            unit TEXT NOT NULL,
-            FOREIGN KEY(plot_id) REFERENCES plot(id)
-        )",
-            (), // empty list of parameters.
-        )?;
-        self.conn.execute(
-            "CREATE UNIQUE INDEX IF NOT EXISTS index_time_series_name
-            ON time_series ( plot_id, name )",
-            (), // empty list of parameters.
-        )?;
+fn name_conflict(kind: &str, name: &str) -> HandlingError {
+    HandlingError::ConstraintViolation {
+        message: format!("A {} named \"{}\" already exists.", kind, name),
+        data: json!({ "Kind": kind, "Name": name }),
+    }
+}
 
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS time_series_entry (
-            time_series_id INTEGER,
-            date TEXT NOT NULL,
-            value REAL NOT NULL,
-            FOREIGN KEY(time_series_id) REFERENCES time_series(id)
-        )",
-            (), // empty list of parameters.
-        )?;
+fn not_found(kind: &'static str, id: i64) -> HandlingError {
+    HandlingError::NotFound { kind, id }
+}
 
-        self.conn.execute(
-            "CREATE UNIQUE INDEX IF NOT EXISTS index_time_series_entry
-            ON time_series_entry ( time_series_id, date)",
-            (), // empty list of parameters.
-        )?;
+fn entry_error(error: Error, time_series_id: i64, time_point: &str) -> HandlingError {
+    if is_unique_violation(&error) {
+        HandlingError::ConstraintViolation {
+            message: format!(
+                "Time series {} already has an entry at {}.",
+                time_series_id, time_point
+            ),
+            data: json!({ "Kind": "entry", "TimeSeriesId": time_series_id, "TimePoint": time_point }),
+        }
+    } else {
+        error.into()
+    }
+}
 
-        Ok(())
+// The time point of an entry to be written, which has to be representable in nanoseconds.
+fn entry_nanos(time_point: &DateTime<Utc>) -> Result<i64, HandlingError> {
+    time_point_to_nanos(time_point).ok_or_else(|| HandlingError::Parse {
+        message: "Time points must be between the years 1677 and 2262.".to_string(),
+        value: time_point_to_string(time_point),
+    })
+}
+
+// A time point to compare stored entries with. Those beyond the representable nanoseconds are
+// clamped, which keeps the comparison intact as no entry can be stored there.
+fn bound_nanos(time_point: &DateTime<Utc>) -> i64 {
+    time_point_to_nanos(time_point).unwrap_or(if time_point.timestamp() < 0 {
+        i64::MIN
+    } else {
+        i64::MAX
+    })
+}
+
+// Binds a range to the (?2), (?3), (?4) parameters of the range filter used by the entry queries.
+fn range_params(range: &TimeRange) -> (Option<i64>, Option<i64>, bool) {
+    (
+        range.start.as_ref().map(bound_nanos),
+        range.end.as_ref().map(bound_nanos),
+        range.end_inclusive,
+    )
+}
+
+// The rank below the percentile among count sorted values and how far the percentile lies towards
+// the next rank. Percentiles interpolate linearly between the closest ranks, as most spreadsheets
+// do it.
+fn percentile_rank(count: i64, percentile: f64) -> (i64, f64) {
+    let rank = percentile / 100.0 * (count - 1) as f64;
+    (rank.floor() as i64, rank - rank.floor())
+}
+
+fn to_sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::String(text) => SqlValue::Text(text.clone()),
+        Value::Number(number) => number.as_i64().map_or(SqlValue::Null, SqlValue::Integer),
+        _ => SqlValue::Null,
+    }
+}
+
+fn file_digest(path: &Path) -> Result<(u64, String), io::Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha1::new();
+    let size = io::copy(&mut file, &mut hasher)?;
+    let digest: String = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok((size, digest))
+}
+
+fn exists(conn: &Connection, table: &str, id: i64) -> Result<bool, Error> {
+    conn.prepare_cached(&format!(
+        "SELECT EXISTS (SELECT 1 FROM {} WHERE id = (?1))",
+        table
+    ))?
+    .query_row(params![id], |row| row.get(0))
+}
+
+fn insert_time_series(
+    tx: &Transaction,
+    plot_id: i64,
+    time_series: &TimeSeries,
+) -> Result<i64, HandlingError> {
+    if time_series.time_points.len() != time_series.values.len() {
+        return Err(HandlingError::Validation(
+            "Inconsistent number of time_points and values in TimeSeries.".to_string(),
+        ));
+    }
+
+    tx.prepare_cached("INSERT INTO time_series (name, plot_id, unit) VALUES (?1, ?2, ?3)")?
+        .execute(params![time_series.name, plot_id, time_series.unit])
+        .map_err(|err| {
+            if is_unique_violation(&err) {
+                name_conflict("time series", &time_series.name)
+            } else {
+                err.into()
+            }
+        })?;
+
+    let new_id: i64 = tx.last_insert_rowid();
+
+    let mut stmt = tx.prepare_cached(ConflictPolicy::Reject.insert_statement())?;
+    for iter in time_series
+        .values
+        .iter()
+        .zip(time_series.time_points.iter())
+    {
+        let (value, time_point) = iter;
+        stmt.execute(params![new_id, entry_nanos(time_point)?, value])
+            .map_err(|err| entry_error(err, new_id, &time_point_to_string(time_point)))?;
+    }
+    Ok(new_id)
+}
+
+// Whether the database has plots, or tables although it is not one of ours. A database that was
+// only migrated holds nothing worth keeping.
+fn holds_data(conn: &Connection) -> Result<bool, HandlingError> {
+    let has_plot_table: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'plot')",
+        (),
+        |row| row.get(0),
+    )?;
+    let query = if has_plot_table {
+        "SELECT EXISTS (SELECT 1 FROM plot)"
+    } else {
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table')"
+    };
+    Ok(conn.query_row(query, (), |row| row.get(0))?)
+}
+
+/// Opens the database file at path for reading only, without migrating it or changing any of its
+/// settings, e.g. as the source of a backup while a server uses the database.
+pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Connection, HandlingError> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    Ok(conn)
+}
+
+/// Writes a consistent snapshot of the source database to a new file at path with SQLite's online
+/// backup. progress is called after every step with the pages copied so far and the total.
+///
+/// The copy runs in one read transaction on source, so with the write-ahead log it sees a fixed
+/// state of the database while other connections keep writing, and never has to start over. It
+/// takes as long as the database is large, so servers run it on a connection and a thread of its
+/// own, see open_read_only.
+pub fn backup<P: AsRef<Path>>(
+    source: &Connection,
+    path: P,
+    mut progress: impl FnMut(i32, i32),
+) -> Result<Snapshot, HandlingError> {
+    let path = path.as_ref();
+    // Backing up into an existing database would silently replace it.
+    if path.exists() {
+        return Err(HandlingError::Validation(format!(
+            "Backup target {} already exists.",
+            path.display()
+        )));
     }
 
-    pub fn new_in_memory() -> Result<Self, Error> {
-        let dao = Self {
+    let pages = {
+        let snapshot = source.unchecked_transaction()?;
+        // The transaction only starts reading with its first statement.
+        snapshot.query_row("SELECT COUNT(*) FROM sqlite_master", (), |row| {
+            row.get::<_, i64>(0)
+        })?;
+        let mut target = Connection::open(path)?;
+        let backup = Backup::new(&snapshot, &mut target)?;
+        let mut retries = 0;
+        loop {
+            let result = backup.step(BACKUP_PAGES_PER_STEP)?;
+            let step = backup.progress();
+            progress(step.pagecount - step.remaining, step.pagecount);
+            match result {
+                StepResult::Done => break step.pagecount,
+                StepResult::More => retries = 0,
+                // Another process holds a lock on the target.
+                _ if retries < 3 => {
+                    retries += 1;
+                    thread::sleep(Duration::from_millis(100));
+                }
+                _ => {
+                    return Err(HandlingError::Internal(format!(
+                        "Backup target {} is locked.",
+                        path.display()
+                    )))
+                }
+            }
+        }
+    };
+
+    let (size, sha1) = file_digest(path).map_err(|err| {
+        HandlingError::Internal(format!("Could not read backup {}: {}", path.display(), err))
+    })?;
+    Ok(Snapshot {
+        path: path.display().to_string(),
+        size,
+        sha1,
+        pages,
+    })
+}
+
+/// Pages of usually 4 KiB copied per backup step, between which the progress is reported.
+const BACKUP_PAGES_PER_STEP: i32 = 1024;
+
+const AUTO_VACUUM_INCREMENTAL: i64 = 2;
+
+/// Entries deleted per transaction of a purge, between which other requests get the DAO.
+pub const PURGE_CHUNK_ENTRIES: usize = 10_000;
+
+/// Free pages given back to the file system after a purge at most, so that a large purge does not
+/// stall the server; the rest follows with the next purges.
+const PURGE_VACUUM_PAGES: i64 = 10_000;
+
+/// Room for every statement of the DAO, so that none has to be prepared twice.
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+pub struct Dao {
+    conn: Connection,
+    /// None for a database in memory.
+    path: Option<PathBuf>,
+}
+
+/// The DAO as shared by the request handlers and background tasks.
+pub type DaoRef = Arc<Mutex<Dao>>;
+
+/// Locks the DAO, also after a panic of a previous holder: rusqlite rolls back the transaction
+/// left open by the panic when it is dropped, so the connection is still consistent.
+pub fn lock(dao: &DaoRef) -> MutexGuard<'_, Dao> {
+    dao.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Dao {
+    pub fn new_in_memory() -> Result<Self, HandlingError> {
+        let mut dao = Self {
             conn: Connection::open_in_memory()?,
+            path: None,
         };
-        dao.set_up()?;
+        dao.conn
+            .set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        dao.conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut dao.conn)?;
         Ok(dao)
     }
 
+    /// Opens the database file at path, creating it if it does not exist yet, and migrates its
+    /// schema to the current version.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, HandlingError> {
+        Self::open_at_version(path, latest_version())
+    }
+
+    /// Like open, but migrates the schema only up to the given version. The queries expect the
+    /// latest version, so older ones are only fit for benchmarks of earlier layouts.
+    pub fn open_at_version<P: AsRef<Path>>(path: P, version: i64) -> Result<Self, HandlingError> {
+        let mut dao = Self {
+            conn: Connection::open(&path)?,
+            path: Some(path.as_ref().to_path_buf()),
+        };
+        // Only takes effect while the database is empty, see enable_incremental_vacuum.
+        dao.conn.pragma_update(None, "auto_vacuum", "INCREMENTAL")?;
+        // The write-ahead log lets readers of other connections, e.g. backups, run alongside
+        // writes. With it, synchronous NORMAL is still safe against corruption and only loses
+        // the last transactions on power failure.
+        let journal_mode: String =
+            dao.conn
+                .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            eprintln!(
+                "Database does not support WAL, using journal mode {}.",
+                journal_mode
+            );
+        }
+        dao.conn
+            .set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        dao.conn.pragma_update(None, "synchronous", "NORMAL")?;
+        dao.conn.pragma_update(None, "foreign_keys", true)?;
+        dao.conn.busy_timeout(Duration::from_secs(5))?;
+        migrate_to(&mut dao.conn, version)?;
+
+        Ok(dao)
+    }
+
+    pub fn has_incremental_vacuum(&self) -> Result<bool, HandlingError> {
+        let auto_vacuum: i64 = self
+            .conn
+            .query_row("PRAGMA auto_vacuum", (), |row| row.get(0))?;
+        Ok(auto_vacuum == AUTO_VACUUM_INCREMENTAL)
+    }
+
+    /// Purges reclaim their space step by step, which needs incremental auto vacuum. Turning it on
+    /// for a database created without it takes one full vacuum, which rewrites the whole file and
+    /// locks it meanwhile, so it is left to an explicit migrate. Returns whether it converted.
+    pub fn enable_incremental_vacuum(&self) -> Result<bool, HandlingError> {
+        if self.has_incremental_vacuum()? {
+            return Ok(false);
+        }
+        self.conn.execute_batch("VACUUM")?;
+        Ok(true)
+    }
+
+    /// Zero turns the statement cache off, which only makes sense to measure what it saves.
+    pub fn set_statement_cache_capacity(&self, capacity: usize) {
+        self.conn.set_prepared_statement_cache_capacity(capacity);
+    }
+
+    /// Replaces the database file at path with the backup and opens it. The backup may be of an
+    /// older schema version, it is migrated like any other database. A database that holds plots
+    /// or tables of its own is only replaced with force.
+    pub fn restore<P: AsRef<Path>, Q: AsRef<Path>>(
+        path: P,
+        backup: Q,
+        force: bool,
+        progress: impl Fn(i32, i32),
+    ) -> Result<Self, HandlingError> {
+        // SQLite would create a missing backup and restore that empty database.
+        if !backup.as_ref().is_file() {
+            return Err(HandlingError::Validation(format!(
+                "Backup {} does not exist.",
+                backup.as_ref().display()
+            )));
+        }
+        if !force && path.as_ref().is_file() && holds_data(&open_read_only(&path)?)? {
+            return Err(HandlingError::Validation(format!(
+                "Database {} already holds data, use --force-restore to replace it.",
+                path.as_ref().display()
+            )));
+        }
+        let mut conn = Connection::open(&path)?;
+        conn.restore(
+            DatabaseName::Main,
+            backup,
+            Some(|step: Progress| progress(step.pagecount - step.remaining, step.pagecount)),
+        )?;
+        drop(conn);
+        Self::open(path)
+    }
+
+    /// The file of the database, from which backups can read with a connection of their own.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Appends the entries within the range to the time series, in chronological order. Returns
+    /// whether entries beyond the page are left.
     pub fn get_entries_for_time_series(
         &self,
         time_series: &mut TimeSeries,
-        start_date: Option<DateTime<Utc>>,
-    ) -> Result<(), HandlingError> {
-        let date_string = start_date
-            .map(|val| time_point_to_string(&val))
-            .unwrap_or("".to_string());
+        range: &TimeRange,
+        page: &Page<DateTime<Utc>>,
+    ) -> Result<bool, HandlingError> {
+        let (start, end, end_inclusive) = range_params(range);
+        let after = page.after.as_ref().map(bound_nanos);
 
-        let mut stmt = self.conn.prepare(
-            "SELECT date, value FROM time_series_entry WHERE time_series_id = (?1) AND date >= (?2) ORDER BY date",
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT date, value FROM time_series_entry WHERE time_series_id = (?1)
+            AND ((?2) IS NULL OR date >= (?2)) AND ((?3) IS NULL OR date < (?3) OR ((?4) AND date = (?3)))
+            AND ((?5) IS NULL OR date > (?5))
+            ORDER BY date LIMIT (?6)",
         )?;
 
-        let entry_iter = stmt.query_map(params![time_series.id, date_string], |row| {
-            Ok(TimeSeriesEntry::new_from_string(
-                &(row.get::<_, String>(0)?),
-                row.get(1)?,
-            ))
+        let entry_iter = stmt.query_map(
+            params![
+                time_series.id,
+                start,
+                end,
+                end_inclusive,
+                after,
+                page.sql_limit()
+            ],
+            |row| Ok(TimeSeriesEntry::new_from_nanos(row.get(0)?, row.get(1)?)),
+        )?;
+
+        for (index, entry) in entry_iter.enumerate() {
+            let entry = entry?;
+            if page.limit.is_some_and(|limit| index >= limit) {
+                return Ok(true);
+            }
+            time_series.time_points.push(entry.time_point);
+            time_series.values.push(entry.value);
+        }
+        Ok(false)
+    }
+
+    /// Appends the last count entries to the time series, in chronological order. Walks the
+    /// entry index backwards, so only the returned entries are read.
+    pub fn get_latest_entries_for_time_series(
+        &self,
+        time_series: &mut TimeSeries,
+        count: usize,
+    ) -> Result<(), HandlingError> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT date, value FROM time_series_entry WHERE time_series_id = (?1)
+            ORDER BY date DESC LIMIT (?2)",
+        )?;
+
+        let entry_iter = stmt.query_map(params![time_series.id, count as i64], |row| {
+            Ok(TimeSeriesEntry::new_from_nanos(row.get(0)?, row.get(1)?))
         })?;
 
+        let mut entries: Vec<TimeSeriesEntry> = vec![];
         for entry in entry_iter {
-            let entry = entry??;
+            entries.push(entry?);
+        }
+
+        for entry in entries.into_iter().rev() {
             time_series.time_points.push(entry.time_point);
             time_series.values.push(entry.value);
         }
         Ok(())
     }
 
+    /// Returns the last entry at or before and the first entry at or after the time point.
+    pub fn get_neighbouring_entries(
+        &self,
+        time_series_id: i64,
+        time_point: &DateTime<Utc>,
+    ) -> Result<(Option<TimeSeriesEntry>, Option<TimeSeriesEntry>), HandlingError> {
+        let nanos = bound_nanos(time_point);
+
+        let mut previous_stmt = self.conn.prepare_cached(
+            "SELECT date, value FROM time_series_entry WHERE time_series_id = (?1) AND date <= (?2)
+            ORDER BY date DESC LIMIT 1",
+        )?;
+        let previous = previous_stmt
+            .query_row(params![time_series_id, nanos], |row| {
+                Ok(TimeSeriesEntry::new_from_nanos(row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+
+        let mut next_stmt = self.conn.prepare_cached(
+            "SELECT date, value FROM time_series_entry WHERE time_series_id = (?1) AND date >= (?2)
+            ORDER BY date LIMIT 1",
+        )?;
+        let next = next_stmt
+            .query_row(params![time_series_id, nanos], |row| {
+                Ok(TimeSeriesEntry::new_from_nanos(row.get(0)?, row.get(1)?))
+            })
+            .optional()?;
+
+        Ok((previous, next))
+    }
+
+    pub fn get_statistics(
+        &self,
+        time_series_id: i64,
+        range: &TimeRange,
+        percentiles: &[f64],
+    ) -> Result<Statistics, HandlingError> {
+        if !exists(&self.conn, "time_series", time_series_id)? {
+            return Err(not_found("time series", time_series_id));
+        }
+        let (start, end, end_inclusive) = range_params(range);
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT COUNT(*), MIN(value), MAX(value), AVG(value), MIN(date), MAX(date)
+            FROM time_series_entry WHERE time_series_id = (?1)
+            AND ((?2) IS NULL OR date >= (?2)) AND ((?3) IS NULL OR date < (?3) OR ((?4) AND date = (?3)))",
+        )?;
+        let (count, min, max, mean, first_date, last_date) =
+            stmt.query_row(params![time_series_id, start, end, end_inclusive], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, Option<f64>>(1)?,
+                    row.get::<_, Option<f64>>(2)?,
+                    row.get::<_, Option<f64>>(3)?,
+                    row.get::<_, Option<i64>>(4)?,
+                    row.get::<_, Option<i64>>(5)?,
+                ))
+            })?;
+
+        // Relative to the mean the squares stay small, which keeps the variance accurate.
+        let standard_deviation = match mean {
+            Some(mean) => self
+                .conn
+                .prepare_cached(
+                    "SELECT AVG((value - (?5)) * (value - (?5))) FROM time_series_entry
+                    WHERE time_series_id = (?1)
+                    AND ((?2) IS NULL OR date >= (?2)) AND ((?3) IS NULL OR date < (?3) OR ((?4) AND date = (?3)))",
+                )?
+                .query_row(
+                    params![time_series_id, start, end, end_inclusive, mean],
+                    |row| row.get::<_, Option<f64>>(0),
+                )?
+                .map(f64::sqrt),
+            None => None,
+        };
+
+        // Only the two closest ranks of each percentile are read, SQLite sorts without handing
+        // over all values.
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT value FROM time_series_entry WHERE time_series_id = (?1)
+            AND ((?2) IS NULL OR date >= (?2)) AND ((?3) IS NULL OR date < (?3) OR ((?4) AND date = (?3)))
+            ORDER BY value LIMIT 2 OFFSET (?5)",
+        )?;
+        let mut percentile_values = vec![];
+        for percentile in percentiles {
+            if count == 0 {
+                percentile_values.push((*percentile, None));
+                continue;
+            }
+            let (rank, fraction) = percentile_rank(count, *percentile);
+            let closest = stmt
+                .query_map(
+                    params![time_series_id, start, end, end_inclusive, rank],
+                    |row| row.get::<_, f64>(0),
+                )?
+                .collect::<Result<Vec<f64>, _>>()?;
+            let value = match closest[..] {
+                [lower, upper] => lower + (upper - lower) * fraction,
+                [lower] => lower,
+                _ => {
+                    return Err(HandlingError::Internal(
+                        "Entries changed while computing statistics".to_string(),
+                    ))
+                }
+            };
+            percentile_values.push((*percentile, Some(value)));
+        }
+
+        Ok(Statistics {
+            time_series_id,
+            count,
+            min,
+            max,
+            mean,
+            standard_deviation,
+            percentiles: percentile_values,
+            first_time_point: first_date.map(time_point_from_nanos),
+            last_time_point: last_date.map(time_point_from_nanos),
+        })
+    }
+
+    /// Aggregates the entries within the range into buckets of bucket_width seconds. Buckets start
+    /// at multiples of bucket_width since the Unix epoch, so adjacent ranges tile without overlap.
+    /// Empty buckets are left out.
+    pub fn get_buckets_for_time_series(
+        &self,
+        time_series_id: i64,
+        range: &TimeRange,
+        bucket_width: i64,
+    ) -> Result<Vec<Bucket>, HandlingError> {
+        let (start, end, end_inclusive) = range_params(range);
+
+        let mut stmt = self.conn.prepare_cached(
+            "WITH bucket AS (
+                SELECT seconds - (((seconds % (?5)) + (?5)) % (?5)) AS start,
+                MIN(value) AS min, MAX(value) AS max, AVG(value) AS mean, SUM(value) AS sum,
+                COUNT(*) AS count, MIN(date) AS first_date, MAX(date) AS last_date
+                FROM (
+                    SELECT (date - (((date % 1000000000) + 1000000000) % 1000000000)) / 1000000000
+                    AS seconds, date, value
+                    FROM time_series_entry WHERE time_series_id = (?1)
+                    AND ((?2) IS NULL OR date >= (?2)) AND ((?3) IS NULL OR date < (?3) OR ((?4) AND date = (?3)))
+                )
+                GROUP BY start
+            )
+            SELECT bucket.start, bucket.min, bucket.max, bucket.mean, bucket.sum, bucket.count,
+            first.value, last.value FROM bucket
+            JOIN time_series_entry AS first
+            ON first.time_series_id = (?1) AND first.date = bucket.first_date
+            JOIN time_series_entry AS last
+            ON last.time_series_id = (?1) AND last.date = bucket.last_date
+            ORDER BY bucket.start",
+        )?;
+
+        let bucket_iter = stmt.query_map(
+            params![time_series_id, start, end, end_inclusive, bucket_width],
+            |row| {
+                Ok(Bucket {
+                    start: Utc
+                        .timestamp_opt(row.get(0)?, 0)
+                        .single()
+                        .unwrap_or(DateTime::<Utc>::MIN_UTC),
+                    min: row.get(1)?,
+                    max: row.get(2)?,
+                    mean: row.get(3)?,
+                    sum: row.get(4)?,
+                    count: row.get(5)?,
+                    first: row.get(6)?,
+                    last: row.get(7)?,
+                })
+            },
+        )?;
+
+        let mut ret_val: Vec<Bucket> = vec![];
+        for bucket in bucket_iter {
+            ret_val.push(bucket?);
+        }
+        Ok(ret_val)
+    }
+
     pub fn get_time_series(&self, id: i64) -> Result<TimeSeries, HandlingError> {
         let mut stmt = self
             .conn
-            .prepare("SELECT id, name, unit FROM time_series WHERE id = (?1)")?;
+            .prepare_cached("SELECT id, plot_id, name, unit FROM time_series WHERE id = (?1)")?;
 
-        let time_series = stmt.query_row(params![id], |row| {
-            Ok(TimeSeries {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                unit: row.get(2)?,
-                time_points: vec![],
-                values: vec![],
+        let time_series = stmt
+            .query_row(params![id], |row| {
+                Ok(TimeSeries {
+                    id: row.get(0)?,
+                    plot_id: row.get(1)?,
+                    name: row.get(2)?,
+                    unit: row.get(3)?,
+                    time_points: vec![],
+                    values: vec![],
+                })
             })
-        })?;
+            .optional()?
+            .ok_or(not_found("time series", id))?;
+
+        Ok(time_series)
+    }
+
+    /// Returns the time series with the entries of the page and whether entries beyond the page are left.
+    pub fn get_time_series_with_data(
+        &self,
+        id: i64,
+        range: &TimeRange,
+        page: &Page<DateTime<Utc>>,
+    ) -> Result<(TimeSeries, bool), HandlingError> {
+        let mut time_series = self.get_time_series(id)?;
+        let has_more = self.get_entries_for_time_series(&mut time_series, range, page)?;
+        Ok((time_series, has_more))
+    }
+
+    /// Returns the time series with the entries within the range plus the closest entry on either
+    /// side of it, so that values at the edges of the range can be interpolated.
+    pub fn get_time_series_with_margin(
+        &self,
+        id: i64,
+        range: &TimeRange,
+    ) -> Result<TimeSeries, HandlingError> {
+        let (mut time_series, _) = self.get_time_series_with_data(id, range, &Page::default())?;
+
+        if let Some(start) = range.start {
+            if let (Some(previous), _) = self.get_neighbouring_entries(id, &start)? {
+                if time_series.time_points.first() != Some(&previous.time_point) {
+                    time_series.time_points.insert(0, previous.time_point);
+                    time_series.values.insert(0, previous.value);
+                }
+            }
+        }
+
+        if let Some(end) = range.end {
+            if let (_, Some(next)) = self.get_neighbouring_entries(id, &end)? {
+                if time_series.time_points.last() != Some(&next.time_point) {
+                    time_series.time_points.push(next.time_point);
+                    time_series.values.push(next.value);
+                }
+            }
+        }
 
         Ok(time_series)
     }
 
     pub fn get_time_series_for_plot(&self, plot: &mut Plot) -> Result<(), HandlingError> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id, name, unit FROM time_series WHERE plot_id = (?1)")?;
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, plot_id, name, unit FROM time_series WHERE plot_id = (?1) ORDER BY id",
+        )?;
         let time_series_iter = stmt.query_map(params![plot.id], |row| {
             Ok(TimeSeries {
                 id: row.get(0)?,
-                name: row.get(1)?,
-                unit: row.get(2)?,
+                plot_id: row.get(1)?,
+                name: row.get(2)?,
+                unit: row.get(3)?,
                 time_points: vec![],
                 values: vec![],
             })
@@ -137,54 +748,134 @@ impl Dao {
     pub fn get_plot(&self, id: i64) -> Result<Plot, HandlingError> {
         let mut stmt = self
             .conn
-            .prepare("SELECT id, name, description FROM plot WHERE id = (?1)")?;
+            .prepare_cached("SELECT id, name, description FROM plot WHERE id = (?1)")?;
 
-        let plot = stmt.query_row(params![id], |row| {
-            Ok(Plot {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                time_series: vec![],
+        let plot = stmt
+            .query_row(params![id], |row| {
+                Ok(Plot {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    description: row.get(2)?,
+                    time_series: vec![],
+                })
             })
-        })?;
+            .optional()?
+            .ok_or(not_found("plot", id))?;
         Ok(plot)
     }
 
-    pub fn get_plot_with_data(
-        &self,
-        id: i64,
-        start_date: Option<DateTime<Utc>>,
-    ) -> Result<Plot, HandlingError> {
+    pub fn get_plot_with_data(&self, id: i64, range: &TimeRange) -> Result<Plot, HandlingError> {
         let mut plot = self.get_plot(id)?;
 
         self.get_time_series_for_plot(&mut plot)?;
 
         for time_series in plot.time_series.iter_mut() {
-            self.get_entries_for_time_series(time_series, start_date);
+            self.get_entries_for_time_series(time_series, range, &Page::default())?;
         }
 
         Ok(plot)
     }
 
-    pub fn get_all_plots(&self) -> Result<Vec<Plot>, HandlingError> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id, name, description FROM plot ORDER BY id")?;
+    /// Lists the plots matching the filter with an overview of their time series, in the given
+    /// order with the id breaking ties. Pages continue after the sort value and id of the last plot
+    /// of the previous page. Returns the plots of the page and whether plots beyond it are left.
+    pub fn get_all_plots(
+        &self,
+        filter: &PlotFilter,
+        order: &PlotOrder,
+        page: &Page<(Value, i64)>,
+    ) -> Result<(Vec<PlotSummary>, bool), HandlingError> {
+        let (operator, escape, pattern): (&str, &str, fn(&String) -> String) =
+            match filter.text_match {
+                TextMatch::Contains => ("LIKE", "ESCAPE '\\'", |text| {
+                    let escaped = text
+                        .replace('\\', "\\\\")
+                        .replace('%', "\\%")
+                        .replace('_', "\\_");
+                    format!("%{}%", escaped)
+                }),
+                TextMatch::Glob => ("GLOB", "", |text| text.clone()),
+            };
+        // Plots without entries get a sort value, because comparing with NULL never holds.
+        let sort_key = match order.key {
+            PlotSortKey::Id => "id",
+            PlotSortKey::Name => "name",
+            PlotSortKey::SeriesCount => "series_count",
+            PlotSortKey::FirstTimePoint => "COALESCE(first_date, -9223372036854775808)",
+            PlotSortKey::LastTimePoint => "COALESCE(last_date, -9223372036854775808)",
+        };
+        let (comparison, direction) = if order.descending {
+            ("<", "DESC")
+        } else {
+            (">", "ASC")
+        };
 
-        let iter = stmt.query_map(params![], |row| {
-            Ok(Plot {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                time_series: vec![],
-            })
-        })?;
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "WITH summary AS (
+                SELECT id, name, description,
+                (SELECT COUNT(*) FROM time_series WHERE plot_id = plot.id) AS series_count,
+                (SELECT MIN((SELECT MIN(date) FROM time_series_entry WHERE time_series_id = time_series.id))
+                    FROM time_series WHERE plot_id = plot.id) AS first_date,
+                (SELECT MAX((SELECT MAX(date) FROM time_series_entry WHERE time_series_id = time_series.id))
+                    FROM time_series WHERE plot_id = plot.id) AS last_date
+                FROM plot
+                WHERE ((?1) IS NULL OR name {operator} (?1) {escape})
+                AND ((?2) IS NULL OR description {operator} (?2) {escape})
+                AND ((?3) IS NULL OR EXISTS (SELECT 1 FROM time_series
+                    WHERE plot_id = plot.id AND name {operator} (?3) {escape}))
+                AND ((?4) IS NULL OR EXISTS (SELECT 1 FROM time_series
+                    WHERE plot_id = plot.id AND unit = (?4)))
+            ), keyed AS (
+                SELECT *, {sort_key} AS sort_key FROM summary
+            )
+            SELECT id, name, description, series_count, first_date, last_date FROM keyed
+            WHERE (?6) IS NULL OR (sort_key, id) {comparison} ((?5), (?6))
+            ORDER BY sort_key {direction}, id {direction} LIMIT (?7)"
+        ))?;
+
+        let (after_key, after_id) = match &page.after {
+            Some((key, id)) => (to_sql_value(key), Some(*id)),
+            None => (SqlValue::Null, None),
+        };
+        let iter = stmt.query_map(
+            params![
+                filter.name.as_ref().map(pattern),
+                filter.description.as_ref().map(pattern),
+                filter.series_name.as_ref().map(pattern),
+                filter.series_unit,
+                after_key,
+                after_id,
+                page.sql_limit()
+            ],
+            |row| {
+                Ok((
+                    Plot {
+                        id: row.get(0)?,
+                        name: row.get(1)?,
+                        description: row.get(2)?,
+                        time_series: vec![],
+                    },
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, Option<i64>>(4)?,
+                    row.get::<_, Option<i64>>(5)?,
+                ))
+            },
+        )?;
 
-        let mut ret_val: Vec<Plot> = vec![];
-        for plot in iter {
-            ret_val.push(plot?);
+        let mut ret_val: Vec<PlotSummary> = vec![];
+        for row in iter {
+            let (plot, series_count, first_date, last_date) = row?;
+            ret_val.push(PlotSummary {
+                plot,
+                series_count,
+                first_time_point: first_date.map(time_point_from_nanos),
+                last_time_point: last_date.map(time_point_from_nanos),
+            });
         }
-        Ok(ret_val)
+
+        let has_more = page.limit.is_some_and(|limit| ret_val.len() > limit);
+        ret_val.truncate(page.limit.unwrap_or(ret_val.len()));
+        Ok((ret_val, has_more))
     }
 
     pub fn add_time_series(
@@ -192,34 +883,38 @@ impl Dao {
         plot_id: i64,
         time_series: &TimeSeries,
     ) -> Result<TimeSeries, HandlingError> {
-        if time_series.time_points.len() != time_series.values.len() {
-            return Err(HandlingError {
-                message: "Inconsistent number of time_points and values in TimeSeries.".to_string(),
-                code: 420,
-            });
+        let mut ret_val = time_series.clone();
+        let tx = self.conn.transaction()?;
+        if !exists(&tx, "plot", plot_id)? {
+            return Err(not_found("plot", plot_id));
         }
+        ret_val.id = insert_time_series(&tx, plot_id, time_series)?;
+        ret_val.plot_id = plot_id;
+        tx.commit()?;
+        Ok(ret_val)
+    }
 
-        let mut ret_val = time_series.clone();
+    pub fn add_plot(&mut self, plot: &Plot) -> Result<Plot, HandlingError> {
+        let mut ret_val = plot.clone();
         let tx = self.conn.transaction()?;
 
-        tx.execute(
-            "INSERT INTO time_series (name, plot_id, unit) VALUES (?1, ?2, ?3)",
-            params![time_series.name.clone(), plot_id, time_series.unit.clone()],
-        )?;
+        tx.prepare_cached("INSERT INTO plot (name, description) VALUES (?1, ?2)")?
+            .execute(params![plot.name, plot.description])
+            .map_err(|err| {
+                if is_unique_violation(&err) {
+                    name_conflict("plot", &plot.name)
+                } else {
+                    err.into()
+                }
+            })?;
 
         let new_id: i64 = tx.last_insert_rowid();
 
-        for iter in time_series
-            .values
-            .iter()
-            .zip(time_series.time_points.iter())
-        {
-            let (value, time_point) = iter;
-            let time_point = time_point_to_string(time_point);
-            tx.execute(
-                "INSERT INTO time_series_entry (time_series_id, date, value) VALUES (?1, ?2, ?3)",
-                params![new_id, time_point, value],
-            )?;
+        // Adding the time series in the same transaction leaves no half-added plot behind on failure.
+        for iter in plot.time_series.iter().zip(ret_val.time_series.iter_mut()) {
+            let (time_series, new_time_series) = iter;
+            new_time_series.id = insert_time_series(&tx, new_id, time_series)?;
+            new_time_series.plot_id = new_id;
         }
 
         tx.commit()?;
@@ -227,41 +922,594 @@ impl Dao {
         Ok(ret_val)
     }
 
-    pub fn add_plot(&mut self, plot: &Plot) -> Result<Plot, HandlingError> {
-        let mut ret_val = plot.clone();
+    /// Writes all entries within a single transaction. Returns the number of entries written,
+    /// which is lower than the number of entries passed if duplicates were skipped.
+    pub fn append_entries(
+        &mut self,
+        entries: &[(i64, TimeSeriesEntry)],
+        on_conflict: ConflictPolicy,
+    ) -> Result<usize, HandlingError> {
         let tx = self.conn.transaction()?;
+        let mut known_time_series: HashSet<i64> = HashSet::new();
+        let mut written: usize = 0;
 
-        tx.execute(
-            "INSERT INTO plot (name, description) VALUES (?1, ?2)",
-            params![plot.name, plot.description],
-        )?;
+        {
+            let mut stmt = tx.prepare_cached(on_conflict.insert_statement())?;
+
+            for (time_series_id, entry) in entries {
+                if known_time_series.insert(*time_series_id)
+                    && !exists(&tx, "time_series", *time_series_id)?
+                {
+                    return Err(not_found("time series", *time_series_id));
+                }
+
+                written += stmt
+                    .execute(params![
+                        time_series_id,
+                        entry_nanos(&entry.time_point)?,
+                        entry.value
+                    ])
+                    .map_err(|err| {
+                        entry_error(
+                            err,
+                            *time_series_id,
+                            &time_point_to_string(&entry.time_point),
+                        )
+                    })?;
+            }
+        }
 
-        let new_id: i64 = tx.last_insert_rowid();
         tx.commit()?;
+        Ok(written)
+    }
 
-        for iter in plot.time_series.iter().zip(ret_val.time_series.iter_mut()) {
-            let (time_series, new_time_series) = iter;
-            let added_series = self.add_time_series(new_id, &time_series)?;
-            new_time_series.id = added_series.id;
+    /// Changes the name and/or description of a plot. Fields passed as None are left as they are.
+    pub fn update_plot(
+        &mut self,
+        id: i64,
+        name: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<Plot, HandlingError> {
+        let updated = self
+            .conn
+            .prepare_cached(
+                "UPDATE plot SET name = COALESCE((?2), name), description = COALESCE((?3), description)
+                WHERE id = (?1)",
+            )?
+            .execute(params![id, name, description])
+            .map_err(|err| {
+                if is_unique_violation(&err) {
+                    name_conflict("plot", name.unwrap_or_default())
+                } else {
+                    err.into()
+                }
+            })?;
+
+        if updated == 0 {
+            return Err(not_found("plot", id));
         }
 
-        ret_val.id = new_id;
-        Ok(ret_val)
+        let mut plot = self.get_plot(id)?;
+        self.get_time_series_for_plot(&mut plot)?;
+        Ok(plot)
     }
 
-    pub fn add_entry(
+    /// Changes the name and/or unit of a time series. Fields passed as None are left as they are.
+    pub fn update_time_series(
+        &mut self,
+        id: i64,
+        name: Option<&str>,
+        unit: Option<&str>,
+    ) -> Result<TimeSeries, HandlingError> {
+        let updated = self
+            .conn
+            .prepare_cached(
+                "UPDATE time_series SET name = COALESCE((?2), name), unit = COALESCE((?3), unit)
+                WHERE id = (?1)",
+            )?
+            .execute(params![id, name, unit])
+            .map_err(|err| {
+                if is_unique_violation(&err) {
+                    name_conflict("time series", name.unwrap_or_default())
+                } else {
+                    err.into()
+                }
+            })?;
+
+        if updated == 0 {
+            return Err(not_found("time series", id));
+        }
+
+        self.get_time_series(id)
+    }
+
+    pub fn delete_plot(&mut self, id: i64) -> Result<DeletedRows, HandlingError> {
+        let tx = self.conn.transaction()?;
+        if !exists(&tx, "plot", id)? {
+            return Err(not_found("plot", id));
+        }
+
+        // Delete bottom-up so that the foreign keys hold at every step.
+        let entries = tx
+            .prepare_cached(
+                "DELETE FROM time_series_entry WHERE time_series_id IN
+            (SELECT id FROM time_series WHERE plot_id = (?1))",
+            )?
+            .execute(params![id])?;
+        let time_series = tx
+            .prepare_cached("DELETE FROM time_series WHERE plot_id = (?1)")?
+            .execute(params![id])?;
+        let plots = tx
+            .prepare_cached("DELETE FROM plot WHERE id = (?1)")?
+            .execute(params![id])?;
+
+        tx.commit()?;
+        Ok(DeletedRows {
+            plots,
+            time_series,
+            entries,
+        })
+    }
+
+    pub fn delete_time_series(&mut self, id: i64) -> Result<DeletedRows, HandlingError> {
+        let tx = self.conn.transaction()?;
+        if !exists(&tx, "time_series", id)? {
+            return Err(not_found("time series", id));
+        }
+
+        let entries = tx
+            .prepare_cached("DELETE FROM time_series_entry WHERE time_series_id = (?1)")?
+            .execute(params![id])?;
+        let time_series = tx
+            .prepare_cached("DELETE FROM time_series WHERE id = (?1)")?
+            .execute(params![id])?;
+
+        tx.commit()?;
+        Ok(DeletedRows {
+            plots: 0,
+            time_series,
+            entries,
+        })
+    }
+
+    pub fn delete_entries(
         &mut self,
         time_series_id: i64,
-        entry: &TimeSeriesEntry,
-    ) -> Result<(), HandlingError> {
-        self.conn.execute(
-            "INSERT INTO time_series_entry (time_series_id, date, value) VALUES (?1, ?2, ?3)",
-            params![
-                time_series_id,
-                time_point_to_string(&entry.time_point),
-                entry.value
-            ],
+        range: &TimeRange,
+    ) -> Result<DeletedRows, HandlingError> {
+        let tx = self.conn.transaction()?;
+        if !exists(&tx, "time_series", time_series_id)? {
+            return Err(not_found("time series", time_series_id));
+        }
+
+        let (start, end, end_inclusive) = range_params(range);
+        let entries = tx
+            .prepare_cached(
+                "DELETE FROM time_series_entry WHERE time_series_id = (?1)
+            AND ((?2) IS NULL OR date >= (?2)) AND ((?3) IS NULL OR date < (?3) OR ((?4) AND date = (?3)))",
+            )?
+            .execute(params![time_series_id, start, end, end_inclusive])?;
+
+        tx.commit()?;
+        Ok(DeletedRows {
+            plots: 0,
+            time_series: 0,
+            entries,
+        })
+    }
+
+    /// Sets the default retention in seconds for the time series of the plot. None keeps their
+    /// entries forever.
+    pub fn set_plot_retention(
+        &mut self,
+        id: i64,
+        retention: Option<i64>,
+    ) -> Result<PlotRetention, HandlingError> {
+        let updated = self
+            .conn
+            .prepare_cached("UPDATE plot SET retention = (?2) WHERE id = (?1)")?
+            .execute(params![id, retention])?;
+        if updated == 0 {
+            return Err(not_found("plot", id));
+        }
+        self.get_retention(id)
+    }
+
+    /// Sets the retention in seconds of the time series. None falls back to the default of its
+    /// plot.
+    pub fn set_time_series_retention(
+        &mut self,
+        id: i64,
+        retention: Option<i64>,
+    ) -> Result<PlotRetention, HandlingError> {
+        let updated = self
+            .conn
+            .prepare_cached("UPDATE time_series SET retention = (?2) WHERE id = (?1)")?
+            .execute(params![id, retention])?;
+        if updated == 0 {
+            return Err(not_found("time series", id));
+        }
+        let plot_id = self.get_time_series(id)?.plot_id;
+        self.get_retention(plot_id)
+    }
+
+    pub fn get_retention(&self, plot_id: i64) -> Result<PlotRetention, HandlingError> {
+        let retention: Option<i64> = self
+            .conn
+            .prepare_cached("SELECT retention FROM plot WHERE id = (?1)")?
+            .query_row(params![plot_id], |row| row.get(0))
+            .optional()?
+            .ok_or(not_found("plot", plot_id))?;
+
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT id, name, retention, purged_entries, last_purge FROM time_series
+            WHERE plot_id = (?1) ORDER BY id",
         )?;
-        Ok(())
+        let series_iter = stmt.query_map(params![plot_id], |row| {
+            let series_retention: Option<i64> = row.get(2)?;
+            Ok(SeriesRetention {
+                time_series_id: row.get(0)?,
+                name: row.get(1)?,
+                retention: series_retention,
+                effective_retention: series_retention.or(retention),
+                purged_entries: row.get(3)?,
+                last_purge: row.get::<_, Option<i64>>(4)?.map(time_point_from_nanos),
+            })
+        })?;
+
+        let mut time_series: Vec<SeriesRetention> = vec![];
+        for series in series_iter {
+            time_series.push(series?);
+        }
+        Ok(PlotRetention {
+            plot_id,
+            retention,
+            time_series,
+        })
+    }
+
+    /// The time series with a retention and the time point before which their entries expire.
+    pub fn expiring_time_series(
+        &self,
+        now: &DateTime<Utc>,
+    ) -> Result<Vec<(i64, i64)>, HandlingError> {
+        let now_nanos = bound_nanos(now);
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT time_series.id, COALESCE(time_series.retention, plot.retention)
+            FROM time_series LEFT JOIN plot ON plot.id = time_series.plot_id
+            WHERE COALESCE(time_series.retention, plot.retention) IS NOT NULL
+            ORDER BY time_series.id",
+        )?;
+        let retention_iter = stmt.query_map((), |row| {
+            let retention: i64 = row.get(1)?;
+            Ok((
+                row.get(0)?,
+                now_nanos.saturating_sub(retention.saturating_mul(1_000_000_000)),
+            ))
+        })?;
+        let mut expiring = vec![];
+        for series in retention_iter {
+            expiring.push(series?);
+        }
+        Ok(expiring)
+    }
+
+    /// Deletes the oldest entries of the time series before cutoff, at most PURGE_CHUNK_ENTRIES of
+    /// them, so that the transaction stays short. Returns the number deleted, fewer than a chunk
+    /// once all expired entries are gone.
+    pub fn purge_chunk(
+        &mut self,
+        time_series_id: i64,
+        cutoff: i64,
+        now: &DateTime<Utc>,
+    ) -> Result<usize, HandlingError> {
+        let tx = self.conn.transaction()?;
+        let deleted = tx
+            .prepare_cached(
+                "DELETE FROM time_series_entry WHERE time_series_id = (?1) AND date IN (
+                    SELECT date FROM time_series_entry WHERE time_series_id = (?1) AND date < (?2)
+                    ORDER BY date LIMIT (?3)
+                )",
+            )?
+            .execute(params![time_series_id, cutoff, PURGE_CHUNK_ENTRIES as i64])?;
+        tx.prepare_cached(
+            "UPDATE time_series SET purged_entries = purged_entries + (?2), last_purge = (?3)
+            WHERE id = (?1)",
+        )?
+        .execute(params![time_series_id, deleted as i64, bound_nanos(now)])?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    /// Gives free pages back to the file system after a purge, at most PURGE_VACUUM_PAGES of them.
+    /// Returns the number of pages given back.
+    pub fn reclaim_free_pages(&mut self) -> Result<i64, HandlingError> {
+        let free_pages = |conn: &Connection| -> Result<i64, Error> {
+            conn.query_row("PRAGMA freelist_count", (), |row| row.get(0))
+        };
+        let before = free_pages(&self.conn)?;
+        {
+            // The pragma frees one page per step, so it has to be stepped through to the end.
+            let mut stmt = self.conn.prepare_cached(&format!(
+                "PRAGMA incremental_vacuum({})",
+                PURGE_VACUUM_PAGES
+            ))?;
+            let mut rows = stmt.query(())?;
+            while rows.next()?.is_some() {}
+        }
+        Ok(before - free_pages(&self.conn)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration as TimeDelta;
+
+    fn origin() -> DateTime<Utc> {
+        Utc.timestamp_opt(1_600_000_000, 0).unwrap()
+    }
+
+    // A plot with one time series of the values at one second apart, returns the series id.
+    fn add_series(dao: &mut Dao, name: &str, values: &[f64]) -> i64 {
+        let plot = dao
+            .add_plot(&Plot {
+                id: 0,
+                name: name.to_string(),
+                description: "".to_string(),
+                time_series: vec![TimeSeries {
+                    id: 0,
+                    plot_id: 0,
+                    name: "series".to_string(),
+                    unit: "".to_string(),
+                    time_points: (0..values.len() as i64)
+                        .map(|second| origin() + TimeDelta::seconds(second))
+                        .collect(),
+                    values: values.to_vec(),
+                }],
+            })
+            .unwrap();
+        plot.time_series[0].id
+    }
+
+    #[test]
+    fn statistics_interpolate_percentiles() {
+        let mut dao = Dao::new_in_memory().unwrap();
+        let id = add_series(&mut dao, "plot", &[4.0, 1.0, 3.0, 2.0, 5.0]);
+
+        let statistics = dao
+            .get_statistics(id, &TimeRange::default(), &[0.0, 50.0, 62.5, 100.0])
+            .unwrap();
+        assert_eq!(statistics.count, 5);
+        assert_eq!(statistics.mean, Some(3.0));
+        assert_eq!(statistics.standard_deviation, Some(2.0_f64.sqrt()));
+        assert_eq!(
+            statistics.percentiles,
+            vec![
+                (0.0, Some(1.0)),
+                (50.0, Some(3.0)),
+                (62.5, Some(3.5)),
+                (100.0, Some(5.0))
+            ]
+        );
+
+        let empty = TimeRange::new(Some(origin() + TimeDelta::days(1)), None, false).unwrap();
+        let statistics = dao.get_statistics(id, &empty, &[50.0]).unwrap();
+        assert_eq!(statistics.count, 0);
+        assert_eq!(statistics.standard_deviation, None);
+        assert_eq!(statistics.percentiles, vec![(50.0, None)]);
+    }
+
+    #[test]
+    fn restore_replaces_databases_with_data_only_with_force() {
+        let directory =
+            std::env::temp_dir().join(format!("rust-json-restore-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (database, snapshot) = (directory.join("data.db"), directory.join("snapshot.db"));
+
+        let mut dao = Dao::open(&database).unwrap();
+        add_series(&mut dao, "plot", &[1.0]);
+        backup(&dao.conn, &snapshot, |_, _| {}).unwrap();
+        add_series(&mut dao, "other plot", &[2.0]);
+        drop(dao);
+
+        let refused = Dao::restore(&database, &snapshot, false, |_, _| {});
+        assert!(matches!(refused, Err(HandlingError::Validation(_))));
+        let dao = Dao::restore(&database, &snapshot, true, |_, _| {}).unwrap();
+        assert_eq!(
+            dao.get_all_plots(
+                &PlotFilter::default(),
+                &PlotOrder::default(),
+                &Page::default()
+            )
+            .unwrap()
+            .0
+            .len(),
+            1
+        );
+        drop(dao);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    // The entries of the time series as pairs of seconds since origin and value.
+    fn entries(dao: &Dao, id: i64) -> Vec<(i64, f64)> {
+        let (time_series, _) = dao
+            .get_time_series_with_data(id, &TimeRange::default(), &Page::default())
+            .unwrap();
+        time_series
+            .time_points
+            .iter()
+            .zip(time_series.values)
+            .map(|(time_point, value)| ((*time_point - origin()).num_seconds(), value))
+            .collect()
+    }
+
+    fn entry(id: i64, second: i64, value: f64) -> (i64, TimeSeriesEntry) {
+        (
+            id,
+            TimeSeriesEntry {
+                time_point: origin() + TimeDelta::seconds(second),
+                value,
+            },
+        )
+    }
+
+    #[test]
+    fn append_entries_follow_the_conflict_policy() {
+        let mut dao = Dao::new_in_memory().unwrap();
+        let id = add_series(&mut dao, "plot", &[0.0, 1.0]);
+
+        let rejected = dao.append_entries(
+            &[entry(id, 2, 20.0), entry(id, 1, 10.0)],
+            ConflictPolicy::Reject,
+        );
+        assert!(matches!(
+            rejected,
+            Err(HandlingError::ConstraintViolation { .. })
+        ));
+        assert_eq!(entries(&dao, id), vec![(0, 0.0), (1, 1.0)]);
+
+        let written = dao
+            .append_entries(
+                &[entry(id, 1, 10.0), entry(id, 2, 20.0)],
+                ConflictPolicy::Skip,
+            )
+            .unwrap();
+        assert_eq!(written, 1);
+        assert_eq!(entries(&dao, id), vec![(0, 0.0), (1, 1.0), (2, 20.0)]);
+
+        let written = dao
+            .append_entries(
+                &[entry(id, 1, 10.0), entry(id, 3, 30.0)],
+                ConflictPolicy::Overwrite,
+            )
+            .unwrap();
+        assert_eq!(written, 2);
+        assert_eq!(
+            entries(&dao, id),
+            vec![(0, 0.0), (1, 10.0), (2, 20.0), (3, 30.0)]
+        );
+    }
+
+    #[test]
+    fn append_entries_roll_back_on_a_missing_time_series() {
+        let mut dao = Dao::new_in_memory().unwrap();
+        let id = add_series(&mut dao, "plot", &[0.0]);
+
+        let result = dao.append_entries(
+            &[entry(id, 1, 1.0), entry(id + 1, 1, 1.0)],
+            ConflictPolicy::Reject,
+        );
+        assert!(matches!(
+            result,
+            Err(HandlingError::NotFound {
+                kind: "time series",
+                ..
+            })
+        ));
+        assert_eq!(entries(&dao, id), vec![(0, 0.0)]);
+    }
+
+    #[test]
+    fn deletes_cascade_and_count_the_rows() {
+        let mut dao = Dao::new_in_memory().unwrap();
+        let first = add_series(&mut dao, "first", &[0.0, 1.0, 2.0]);
+        let second = add_series(&mut dao, "second", &[0.0, 1.0]);
+        let plot_id = dao.get_time_series(second).unwrap().plot_id;
+
+        let range = TimeRange::new(Some(origin() + TimeDelta::seconds(1)), None, false).unwrap();
+        let deleted = dao.delete_entries(first, &range).unwrap();
+        assert_eq!(
+            (deleted.plots, deleted.time_series, deleted.entries),
+            (0, 0, 2)
+        );
+        assert_eq!(entries(&dao, first), vec![(0, 0.0)]);
+
+        let deleted = dao.delete_time_series(first).unwrap();
+        assert_eq!(
+            (deleted.plots, deleted.time_series, deleted.entries),
+            (0, 1, 1)
+        );
+
+        let deleted = dao.delete_plot(plot_id).unwrap();
+        assert_eq!(
+            (deleted.plots, deleted.time_series, deleted.entries),
+            (1, 1, 2)
+        );
+        assert!(matches!(
+            dao.get_time_series(second),
+            Err(HandlingError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn deletes_of_missing_ids_change_nothing() {
+        let mut dao = Dao::new_in_memory().unwrap();
+        let id = add_series(&mut dao, "plot", &[0.0, 1.0]);
+        let plot_id = dao.get_time_series(id).unwrap().plot_id;
+
+        assert!(matches!(
+            dao.delete_plot(plot_id + 1),
+            Err(HandlingError::NotFound { kind: "plot", .. })
+        ));
+        assert!(matches!(
+            dao.delete_time_series(id + 1),
+            Err(HandlingError::NotFound {
+                kind: "time series",
+                ..
+            })
+        ));
+        assert!(matches!(
+            dao.delete_entries(id + 1, &TimeRange::default()),
+            Err(HandlingError::NotFound {
+                kind: "time series",
+                ..
+            })
+        ));
+        assert_eq!(entries(&dao, id), vec![(0, 0.0), (1, 1.0)]);
+    }
+
+    #[test]
+    fn purges_delete_before_the_cutoff_and_count_the_entries() {
+        let mut dao = Dao::new_in_memory().unwrap();
+        let id = add_series(&mut dao, "plot", &[0.0, 1.0, 2.0, 3.0, 4.0]);
+        let plot_id = dao.get_time_series(id).unwrap().plot_id;
+        dao.set_plot_retention(plot_id, Some(2)).unwrap();
+
+        let now = origin() + TimeDelta::seconds(4);
+        let expiring = dao.expiring_time_series(&now).unwrap();
+        assert_eq!(
+            expiring,
+            vec![(id, bound_nanos(&(origin() + TimeDelta::seconds(2))))]
+        );
+        assert_eq!(dao.purge_chunk(id, expiring[0].1, &now).unwrap(), 2);
+        assert_eq!(entries(&dao, id), vec![(2, 2.0), (3, 3.0), (4, 4.0)]);
+
+        let now = origin() + TimeDelta::seconds(5);
+        let (_, cutoff) = dao.expiring_time_series(&now).unwrap()[0];
+        assert_eq!(dao.purge_chunk(id, cutoff, &now).unwrap(), 1);
+        let retention = dao.get_retention(plot_id).unwrap();
+        assert_eq!(retention.time_series[0].purged_entries, 3);
+        assert_eq!(retention.time_series[0].last_purge, Some(now));
+    }
+
+    #[test]
+    fn purges_stop_after_a_chunk() {
+        let mut dao = Dao::new_in_memory().unwrap();
+        let id = add_series(&mut dao, "plot", &vec![0.0; PURGE_CHUNK_ENTRIES + 1]);
+        let now = origin() + TimeDelta::days(1);
+
+        assert_eq!(
+            dao.purge_chunk(id, bound_nanos(&now), &now).unwrap(),
+            PURGE_CHUNK_ENTRIES
+        );
+        assert_eq!(dao.purge_chunk(id, bound_nanos(&now), &now).unwrap(), 1);
+        assert_eq!(dao.purge_chunk(id, bound_nanos(&now), &now).unwrap(), 0);
+        let plot_id = dao.get_time_series(id).unwrap().plot_id;
+        assert_eq!(
+            dao.get_retention(plot_id).unwrap().time_series[0].purged_entries,
+            PURGE_CHUNK_ENTRIES as i64 + 1
+        );
     }
 }
@@ -1,10 +1,11 @@
 use crate::errors::HandlingError;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeZone, Utc};
 use serde_json::{json, Value};
 
 #[derive(Debug, Clone)]
 pub struct TimeSeries {
     pub id: i64,
+    pub plot_id: i64,
     pub name: String,
     pub unit: String,
     pub time_points: Vec<DateTime<Utc>>,
@@ -23,9 +24,9 @@ pub fn time_point_from_str(string: &str) -> Result<DateTime<Utc>, HandlingError>
     if let Ok(val) = DateTime::parse_from_rfc3339(string) {
         Ok(val.into())
     } else {
-        Err(HandlingError {
+        Err(HandlingError::Parse {
             message: "Could not parse date.".to_string(),
-            code: 430,
+            value: string.to_string(),
         })
     }
 }
@@ -34,7 +35,32 @@ pub fn time_point_to_string(time_point: &DateTime<Utc>) -> String {
     time_point.to_rfc3339()
 }
 
+/// Nanoseconds since the epoch, as time points are stored. Returns None for time points before
+/// 1677 or after 2262, which do not fit into an i64.
+pub fn time_point_to_nanos(time_point: &DateTime<Utc>) -> Option<i64> {
+    time_point
+        .timestamp()
+        .checked_mul(1_000_000_000)?
+        .checked_add(time_point.timestamp_subsec_nanos() as i64)
+}
+
+pub fn time_point_from_nanos(nanos: i64) -> DateTime<Utc> {
+    Utc.timestamp_nanos(nanos)
+}
+
+// Ids are assigned by the database, so objects which are about to be added may omit them.
+fn to_id(json: &Value) -> Option<i64> {
+    if json.is_null() {
+        return Some(0);
+    }
+    json.as_i64()
+}
+
 fn to_f64_vec(json: &Value) -> Option<Vec<f64>> {
+    if json.is_null() {
+        return Some(vec![]);
+    }
+
     let mut ret_val: Vec<f64> = Vec::new();
     if let Some(array) = json.as_array() {
         for entry in array {
@@ -50,6 +76,10 @@ fn to_f64_vec(json: &Value) -> Option<Vec<f64>> {
 }
 
 fn to_datetime_vec(json: &Value) -> Option<Vec<DateTime<Utc>>> {
+    if json.is_null() {
+        return Some(vec![]);
+    }
+
     let mut ret_val: Vec<DateTime<Utc>> = Vec::new();
     if let Some(array) = json.as_array() {
         for entry in array {
@@ -83,29 +113,32 @@ impl TryFrom<&Value> for TimeSeries {
     type Error = ();
 
     fn try_from(item: &Value) -> Result<Self, Self::Error> {
-        let id = item["Id"].as_i64().ok_or(())?;
+        let id = to_id(&item["Id"]).ok_or(())?;
+        let plot_id = to_id(&item["PlotId"]).ok_or(())?;
         let name = item["Name"].as_str().ok_or(())?;
         let unit = item["Unit"].as_str().ok_or(())?;
         let time_points = to_datetime_vec(&item["TimePoints"]).ok_or(())?;
         let values = to_f64_vec(&item["Values"]).ok_or(())?;
         Ok(TimeSeries {
-            id: id,
+            id,
+            plot_id,
             name: name.to_string(),
             unit: unit.to_string(),
-            time_points: time_points,
-            values: values,
+            time_points,
+            values,
         })
     }
 }
 
-impl<'a> Into<Value> for &'a TimeSeries {
-    fn into(self) -> Value {
+impl From<&TimeSeries> for Value {
+    fn from(time_series: &TimeSeries) -> Self {
         json!( {
-        "Id": self.id,
-        "Name": self.name,
-        "Unit": self.unit,
-        "TimePoints": to_json_array(&self.time_points),
-        "Values": json!(self.values)
+        "Id": time_series.id,
+        "PlotId": time_series.plot_id,
+        "Name": time_series.name,
+        "Unit": time_series.unit,
+        "TimePoints": to_json_array(&time_series.time_points),
+        "Values": json!(time_series.values)
         })
     }
 }
@@ -134,36 +167,486 @@ impl TryFrom<&Value> for Plot {
     type Error = ();
 
     fn try_from(item: &Value) -> Result<Self, Self::Error> {
-        let id = item["Id"].as_i64().ok_or(())?;
+        let id = to_id(&item["Id"]).ok_or(())?;
         let name = item["Name"].as_str().ok_or(())?;
         let description = item["Description"].as_str().ok_or(())?;
         let time_series = to_timeseries_vec(&item["TimeSeries"]).ok_or(())?;
 
         Ok(Plot {
-            id: id,
+            id,
             name: name.to_string(),
             description: description.to_string(),
-            time_series: time_series,
+            time_series,
         })
     }
 }
 
-impl<'a> Into<Value> for &'a Plot {
-    fn into(self) -> Value {
-        let time_series_jsons: Vec<Value> = self
+impl From<&Plot> for Value {
+    fn from(plot: &Plot) -> Self {
+        let time_series_jsons: Vec<Value> = plot
             .time_series
             .iter()
             .map(|series| series.into())
             .collect();
         json!( {
-        "Id": self.id,
-        "Name": self.name,
-        "Description": self.description,
+        "Id": plot.id,
+        "Name": plot.name,
+        "Description": plot.description,
         "TimeSeries": time_series_jsons
         })
     }
 }
 
+/// A time range with an inclusive start. Missing bounds leave the range open on that side.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeRange {
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+    pub end_inclusive: bool,
+}
+
+impl TimeRange {
+    pub fn new(
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        end_inclusive: bool,
+    ) -> Result<Self, HandlingError> {
+        if let (Some(start), Some(end)) = (start, end) {
+            if end < start {
+                return Err(HandlingError::Validation(
+                    "EndDate must not be before StartDate.".to_string(),
+                ));
+            }
+        }
+
+        Ok(Self {
+            start,
+            end,
+            end_inclusive,
+        })
+    }
+    pub fn contains(&self, time_point: &DateTime<Utc>) -> bool {
+        let after_start = self.start.is_none_or(|start| *time_point >= start);
+        let before_end = self
+            .end
+            .is_none_or(|end| *time_point < end || (self.end_inclusive && *time_point == end));
+        after_start && before_end
+    }
+}
+
+/// Restricts a query to at most limit rows after the position of the previous page.
+#[derive(Debug, Clone, Copy)]
+pub struct Page<T> {
+    pub after: Option<T>,
+    pub limit: Option<usize>,
+}
+
+impl<T> Default for Page<T> {
+    fn default() -> Self {
+        Self {
+            after: None,
+            limit: None,
+        }
+    }
+}
+
+impl<T> Page<T> {
+    /// One row more than the limit is queried to find out whether another page follows.
+    /// A negative limit makes SQLite return all rows.
+    pub fn sql_limit(&self) -> i64 {
+        // Limits beyond what SQLite can count are no limit at all.
+        self.limit
+            .and_then(|limit| i64::try_from(limit).ok())
+            .and_then(|limit| limit.checked_add(1))
+            .unwrap_or(-1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextMatch {
+    /// Case-insensitive (ASCII only) substring match.
+    #[default]
+    Contains,
+    /// Case-sensitive match of a pattern with the wildcards *, ? and [...].
+    Glob,
+}
+
+/// Restricts a plot listing. Unset filters match every plot.
+#[derive(Debug, Clone, Default)]
+pub struct PlotFilter {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Matches plots with at least one time series whose name matches, like the other texts.
+    pub series_name: Option<String>,
+    /// Matches plots with at least one time series of exactly that unit.
+    pub series_unit: Option<String>,
+    pub text_match: TextMatch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlotSortKey {
+    #[default]
+    Id,
+    Name,
+    SeriesCount,
+    FirstTimePoint,
+    LastTimePoint,
+}
+
+impl PlotSortKey {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlotSortKey::Id => "Id",
+            PlotSortKey::Name => "Name",
+            PlotSortKey::SeriesCount => "SeriesCount",
+            PlotSortKey::FirstTimePoint => "FirstTimePoint",
+            PlotSortKey::LastTimePoint => "LastTimePoint",
+        }
+    }
+}
+
+impl TryFrom<&Value> for PlotSortKey {
+    type Error = ();
+
+    fn try_from(item: &Value) -> Result<Self, Self::Error> {
+        match item.as_str().ok_or(())? {
+            "Id" => Ok(PlotSortKey::Id),
+            "Name" => Ok(PlotSortKey::Name),
+            "SeriesCount" => Ok(PlotSortKey::SeriesCount),
+            "FirstTimePoint" => Ok(PlotSortKey::FirstTimePoint),
+            "LastTimePoint" => Ok(PlotSortKey::LastTimePoint),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlotOrder {
+    pub key: PlotSortKey,
+    pub descending: bool,
+}
+
+/// A plot without its time series but with an overview of them.
+#[derive(Debug, Clone)]
+pub struct PlotSummary {
+    pub plot: Plot,
+    pub series_count: i64,
+    /// The earliest and the latest entry over all time series of the plot.
+    pub first_time_point: Option<DateTime<Utc>>,
+    pub last_time_point: Option<DateTime<Utc>>,
+}
+
+impl PlotSummary {
+    /// The value the plot is sorted by, which is what a cursor has to remember. Plots without
+    /// entries sort as the smallest time point, i.e. before all others.
+    pub fn sort_value(&self, key: PlotSortKey) -> Value {
+        let time_point = |time_point: &Option<DateTime<Utc>>| {
+            time_point
+                .as_ref()
+                .and_then(time_point_to_nanos)
+                .unwrap_or(i64::MIN)
+        };
+        match key {
+            PlotSortKey::Id => self.plot.id.into(),
+            PlotSortKey::Name => self.plot.name.clone().into(),
+            PlotSortKey::SeriesCount => self.series_count.into(),
+            PlotSortKey::FirstTimePoint => time_point(&self.first_time_point).into(),
+            PlotSortKey::LastTimePoint => time_point(&self.last_time_point).into(),
+        }
+    }
+}
+
+impl From<&PlotSummary> for Value {
+    fn from(summary: &PlotSummary) -> Self {
+        let mut json: Value = (&summary.plot).into();
+        json["SeriesCount"] = summary.series_count.into();
+        json["FirstTimePoint"] = summary
+            .first_time_point
+            .as_ref()
+            .map(time_point_to_string)
+            .into();
+        json["LastTimePoint"] = summary
+            .last_time_point
+            .as_ref()
+            .map(time_point_to_string)
+            .into();
+        json
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Min,
+    Max,
+    Mean,
+    Sum,
+    Count,
+    First,
+    Last,
+}
+
+impl Aggregate {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aggregate::Min => "Min",
+            Aggregate::Max => "Max",
+            Aggregate::Mean => "Mean",
+            Aggregate::Sum => "Sum",
+            Aggregate::Count => "Count",
+            Aggregate::First => "First",
+            Aggregate::Last => "Last",
+        }
+    }
+}
+
+impl TryFrom<&Value> for Aggregate {
+    type Error = ();
+
+    fn try_from(item: &Value) -> Result<Self, Self::Error> {
+        match item.as_str().ok_or(())? {
+            "Min" => Ok(Aggregate::Min),
+            "Max" => Ok(Aggregate::Max),
+            "Mean" => Ok(Aggregate::Mean),
+            "Sum" => Ok(Aggregate::Sum),
+            "Count" => Ok(Aggregate::Count),
+            "First" => Ok(Aggregate::First),
+            "Last" => Ok(Aggregate::Last),
+            _ => Err(()),
+        }
+    }
+}
+
+/// All aggregates of the entries within [start, start + bucket width).
+#[derive(Debug, Clone)]
+pub struct Bucket {
+    pub start: DateTime<Utc>,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub sum: f64,
+    pub count: i64,
+    pub first: f64,
+    pub last: f64,
+}
+
+impl Bucket {
+    pub fn get(&self, aggregate: Aggregate) -> Value {
+        match aggregate {
+            Aggregate::Min => json!(self.min),
+            Aggregate::Max => json!(self.max),
+            Aggregate::Mean => json!(self.mean),
+            Aggregate::Sum => json!(self.sum),
+            Aggregate::Count => json!(self.count),
+            Aggregate::First => json!(self.first),
+            Aggregate::Last => json!(self.last),
+        }
+    }
+}
+
+/// A time series whose entries have been reduced to one row of aggregates per bucket.
+#[derive(Debug, Clone)]
+pub struct AggregatedTimeSeries {
+    pub time_series: TimeSeries,
+    pub bucket_width: i64,
+    pub aggregates: Vec<Aggregate>,
+    pub buckets: Vec<Bucket>,
+}
+
+impl From<&AggregatedTimeSeries> for Value {
+    fn from(aggregated: &AggregatedTimeSeries) -> Self {
+        let time_points: Vec<DateTime<Utc>> = aggregated
+            .buckets
+            .iter()
+            .map(|bucket| bucket.start)
+            .collect();
+        let mut columns = serde_json::Map::new();
+        for aggregate in aggregated.aggregates.iter() {
+            let column: Vec<Value> = aggregated
+                .buckets
+                .iter()
+                .map(|bucket| bucket.get(*aggregate))
+                .collect();
+            columns.insert(aggregate.name().to_string(), Value::Array(column));
+        }
+
+        json!( {
+        "Id": aggregated.time_series.id,
+        "PlotId": aggregated.time_series.plot_id,
+        "Name": aggregated.time_series.name,
+        "Unit": aggregated.time_series.unit,
+        "BucketWidth": aggregated.bucket_width,
+        "TimePoints": to_json_array(&time_points),
+        "Aggregates": columns
+        })
+    }
+}
+
+/// Descriptive statistics of the entries of a time series within a range. All fields but count
+/// are None if the range holds no entries.
+#[derive(Debug, Clone)]
+pub struct Statistics {
+    pub time_series_id: i64,
+    pub count: i64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    /// The population standard deviation.
+    pub standard_deviation: Option<f64>,
+    /// Pairs of percentile (0 to 100) and value.
+    pub percentiles: Vec<(f64, Option<f64>)>,
+    pub first_time_point: Option<DateTime<Utc>>,
+    pub last_time_point: Option<DateTime<Utc>>,
+}
+
+impl From<&Statistics> for Value {
+    fn from(statistics: &Statistics) -> Self {
+        let percentiles: Vec<Value> = statistics
+            .percentiles
+            .iter()
+            .map(|(percentile, value)| json!({ "Percentile": percentile, "Value": value }))
+            .collect();
+        json!( {
+        "TimeSeriesId": statistics.time_series_id,
+        "Count": statistics.count,
+        "Min": statistics.min,
+        "Max": statistics.max,
+        "Mean": statistics.mean,
+        "StandardDeviation": statistics.standard_deviation,
+        "Percentiles": percentiles,
+        "FirstTimePoint": statistics.first_time_point.as_ref().map(time_point_to_string),
+        "LastTimePoint": statistics.last_time_point.as_ref().map(time_point_to_string)
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeletedRows {
+    pub plots: usize,
+    pub time_series: usize,
+    pub entries: usize,
+}
+
+impl From<&DeletedRows> for Value {
+    fn from(deleted: &DeletedRows) -> Self {
+        json!( {
+        "DeletedPlots": deleted.plots,
+        "DeletedTimeSeries": deleted.time_series,
+        "DeletedEntries": deleted.entries
+        })
+    }
+}
+
+/// The retention of a time series: entries older than that many seconds are purged.
+#[derive(Debug, Clone)]
+pub struct SeriesRetention {
+    pub time_series_id: i64,
+    pub name: String,
+    /// The retention set for the series itself.
+    pub retention: Option<i64>,
+    /// The retention applied, i.e. the one of the series or else the default of its plot.
+    pub effective_retention: Option<i64>,
+    /// All entries purged from the series so far.
+    pub purged_entries: i64,
+    pub last_purge: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlotRetention {
+    pub plot_id: i64,
+    /// The default for the time series of the plot without a retention of their own.
+    pub retention: Option<i64>,
+    pub time_series: Vec<SeriesRetention>,
+}
+
+impl From<&PlotRetention> for Value {
+    fn from(plot: &PlotRetention) -> Self {
+        let time_series: Vec<Value> = plot
+            .time_series
+            .iter()
+            .map(|series| {
+                json!( {
+                "Id": series.time_series_id,
+                "Name": series.name,
+                "Retention": series.retention,
+                "EffectiveRetention": series.effective_retention,
+                "PurgedEntries": series.purged_entries,
+                "LastPurge": series.last_purge.as_ref().map(time_point_to_string)
+                })
+            })
+            .collect();
+        json!( {
+        "PlotId": plot.plot_id,
+        "Retention": plot.retention,
+        "TimeSeries": time_series
+        })
+    }
+}
+
+/// The outcome of one purge of all time series with a retention.
+#[derive(Debug, Clone)]
+pub struct PurgeReport {
+    /// Pairs of time series id and the number of entries deleted from it.
+    pub time_series: Vec<(i64, usize)>,
+    /// Free database pages given back to the file system afterwards.
+    pub reclaimed_pages: i64,
+}
+
+impl PurgeReport {
+    pub fn deleted_entries(&self) -> usize {
+        self.time_series.iter().map(|(_, entries)| entries).sum()
+    }
+}
+
+/// A backup file written by the database.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub path: String,
+    pub size: u64,
+    /// The SHA-1 of the file, hex encoded, to check copies of it against.
+    pub sha1: String,
+    pub pages: i32,
+}
+
+impl From<&Snapshot> for Value {
+    fn from(snapshot: &Snapshot) -> Self {
+        json!( {
+        "Path": snapshot.path,
+        "Size": snapshot.size,
+        "Sha1": snapshot.sha1,
+        "Pages": snapshot.pages
+        })
+    }
+}
+
+/// Where a backup running in the background stands.
+#[derive(Debug, Clone)]
+pub enum BackupState {
+    Running { copied: i32, total: i32 },
+    Done(Snapshot),
+    Failed(String),
+}
+
+impl From<&BackupState> for Value {
+    fn from(state: &BackupState) -> Self {
+        match state {
+            BackupState::Running { copied, total } => json!( {
+            "State": "Running",
+            "CopiedPages": copied,
+            "Pages": total
+            }),
+            BackupState::Done(snapshot) => {
+                let mut value: Value = snapshot.into();
+                value["State"] = json!("Done");
+                value
+            }
+            BackupState::Failed(message) => json!( {
+            "State": "Failed",
+            "Error": message
+            }),
+        }
+    }
+}
+
 // Only for convenience
 #[derive(Debug, Clone)]
 pub struct TimeSeriesEntry {
@@ -172,17 +655,40 @@ pub struct TimeSeriesEntry {
 }
 
 impl TimeSeriesEntry {
-    pub fn new_from_string(time_point: &str, value: f64) -> Result<Self, HandlingError> {
-        if let Ok(time_point) = time_point_from_str(time_point) {
-            Ok(Self {
-                time_point: time_point,
-                value: value,
-            })
-        } else {
-            Err(HandlingError {
-                message: "Date could not be parsed.".to_string(),
-                code: 410,
-            })
+    pub fn new_from_nanos(nanos: i64, value: f64) -> Self {
+        Self {
+            time_point: time_point_from_nanos(nanos),
+            value,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sql_limit_queries_one_row_more() {
+        let page: Page<i64> = Page {
+            after: None,
+            limit: Some(10),
+        };
+        assert_eq!(page.sql_limit(), 11);
+        assert_eq!(Page::<i64>::default().sql_limit(), -1);
+    }
+
+    #[test]
+    fn sql_limit_does_not_overflow() {
+        let page: Page<i64> = Page {
+            after: None,
+            limit: Some(i64::MAX as usize),
+        };
+        assert_eq!(page.sql_limit(), -1);
+
+        let page: Page<i64> = Page {
+            after: None,
+            limit: Some(usize::MAX),
+        };
+        assert_eq!(page.sql_limit(), -1);
+    }
+}
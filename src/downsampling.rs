@@ -0,0 +1,194 @@
+use crate::data_model::TimeSeries;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Downsampling {
+    /// Largest-Triangle-Three-Buckets, which keeps the points that shape the curve the most.
+    Lttb,
+    /// The minimum and the maximum of every pixel column, which keeps all peaks.
+    MinMax,
+}
+
+/// Reduces the entries of the time series to at most max_points points. Time series which
+/// already fit are left as they are.
+pub fn downsample(time_series: &mut TimeSeries, max_points: usize, downsampling: Downsampling) {
+    if time_series.values.len() <= max_points {
+        return;
+    }
+
+    let indices = match downsampling {
+        Downsampling::Lttb => lttb(&time_series.time_points, &time_series.values, max_points),
+        Downsampling::MinMax => min_max(&time_series.time_points, &time_series.values, max_points),
+    };
+
+    time_series.time_points = indices
+        .iter()
+        .map(|index| time_series.time_points[*index])
+        .collect();
+    time_series.values = indices
+        .iter()
+        .map(|index| time_series.values[*index])
+        .collect();
+}
+
+// Seconds relative to the first time point, which keeps the precision of the f64 high.
+fn to_x(time_point: &DateTime<Utc>, origin: &DateTime<Utc>) -> f64 {
+    let duration = *time_point - *origin;
+    match duration.num_nanoseconds() {
+        Some(nanos) => nanos as f64 * 1e-9,
+        None => duration.num_milliseconds() as f64 * 1e-3,
+    }
+}
+
+// Returns the indices of the selected points. Expects more than max_points points.
+fn lttb(time_points: &[DateTime<Utc>], values: &[f64], max_points: usize) -> Vec<usize> {
+    let len = values.len();
+    if max_points < 3 {
+        return [0, len - 1][..max_points.min(2)].to_vec();
+    }
+
+    let origin = &time_points[0];
+    let x: Vec<f64> = time_points
+        .iter()
+        .map(|time_point| to_x(time_point, origin))
+        .collect();
+
+    // The first and the last point are always kept, the others are split into equally sized buckets.
+    let bucket_size = (len - 2) as f64 / (max_points - 2) as f64;
+    let mut indices: Vec<usize> = Vec::with_capacity(max_points);
+    let mut selected = 0;
+    indices.push(selected);
+
+    for bucket in 0..max_points - 2 {
+        let start = (bucket as f64 * bucket_size) as usize + 1;
+        let end = ((bucket + 1) as f64 * bucket_size) as usize + 1;
+
+        // The third corner of the triangle is the average of the next bucket.
+        let next_start = end;
+        let next_end = (((bucket + 2) as f64 * bucket_size) as usize + 1).min(len);
+        let next_len = (next_end - next_start) as f64;
+        let average_x = x[next_start..next_end].iter().sum::<f64>() / next_len;
+        let average_y = values[next_start..next_end].iter().sum::<f64>() / next_len;
+
+        let (selected_x, selected_y) = (x[selected], values[selected]);
+        let mut max_area = -1.0;
+        for index in start..end {
+            let area = ((selected_x - average_x) * (values[index] - selected_y)
+                - (selected_x - x[index]) * (average_y - selected_y))
+                .abs();
+            if area > max_area {
+                max_area = area;
+                selected = index;
+            }
+        }
+        indices.push(selected);
+    }
+
+    indices.push(len - 1);
+    indices
+}
+
+// Returns the indices of the selected points. Expects more than max_points points.
+fn min_max(time_points: &[DateTime<Utc>], values: &[f64], max_points: usize) -> Vec<usize> {
+    let len = values.len();
+    let columns = max_points / 2;
+    if columns == 0 {
+        return vec![0; max_points.min(1)];
+    }
+
+    let origin = &time_points[0];
+    let width = to_x(&time_points[len - 1], origin) / columns as f64;
+    let mut indices: Vec<usize> = Vec::with_capacity(max_points);
+
+    let mut start = 0;
+    for column in 0..columns {
+        let column_end = (column + 1) as f64 * width;
+        let mut end = start;
+        while end < len && (column + 1 == columns || to_x(&time_points[end], origin) < column_end) {
+            end += 1;
+        }
+        if end == start {
+            continue;
+        }
+
+        let (mut min, mut max) = (start, start);
+        for index in start..end {
+            if values[index] < values[min] {
+                min = index;
+            }
+            if values[index] > values[max] {
+                max = index;
+            }
+        }
+
+        // Keep the points in chronological order.
+        indices.push(min.min(max));
+        if min != max {
+            indices.push(min.max(max));
+        }
+        start = end;
+    }
+
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn time_points(len: i64) -> Vec<DateTime<Utc>> {
+        let origin = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        (0..len)
+            .map(|second| origin + Duration::seconds(second))
+            .collect()
+    }
+
+    fn is_increasing(indices: &[usize]) -> bool {
+        indices.windows(2).all(|pair| pair[0] < pair[1])
+    }
+
+    #[test]
+    fn lttb_keeps_first_and_last_in_order() {
+        let values: Vec<f64> = (0..100).map(|index| (index as f64 / 5.0).sin()).collect();
+        let indices = lttb(&time_points(100), &values, 10);
+        assert_eq!(indices.len(), 10);
+        assert_eq!(indices.first(), Some(&0));
+        assert_eq!(indices.last(), Some(&99));
+        assert!(is_increasing(&indices));
+    }
+
+    #[test]
+    fn lttb_picks_the_outlier_of_a_bucket() {
+        let mut values = vec![0.0; 20];
+        values[7] = 10.0;
+        let indices = lttb(&time_points(20), &values, 5);
+        assert!(indices.contains(&7));
+    }
+
+    #[test]
+    fn min_max_keeps_the_extremes_in_order() {
+        let mut values: Vec<f64> = (0..100).map(|index| (index % 10) as f64).collect();
+        values[42] = 100.0;
+        values[57] = -100.0;
+        let indices = min_max(&time_points(100), &values, 10);
+        assert!(indices.len() <= 10);
+        assert!(indices.contains(&42));
+        assert!(indices.contains(&57));
+        assert!(is_increasing(&indices));
+    }
+
+    #[test]
+    fn downsample_leaves_small_series() {
+        let mut time_series = TimeSeries {
+            id: 0,
+            plot_id: 0,
+            name: "series".to_string(),
+            unit: "".to_string(),
+            time_points: time_points(3),
+            values: vec![1.0, 2.0, 3.0],
+        };
+        downsample(&mut time_series, 3, Downsampling::Lttb);
+        assert_eq!(time_series.values, vec![1.0, 2.0, 3.0]);
+    }
+}
@@ -1,16 +1,123 @@
 use rusqlite::Error as SqlError;
+use serde_json::{json, Value};
+use std::fmt;
 
+// Error codes defined by the JSON-RPC 2.0 specification.
+pub const PARSE_ERROR: i32 = -32700;
+pub const INVALID_REQUEST: i32 = -32600;
+pub const METHOD_NOT_FOUND: i32 = -32601;
+pub const INVALID_PARAMS: i32 = -32602;
+pub const INTERNAL_ERROR: i32 = -32603;
+
+// Application error codes, taken from the range the specification reserves for servers.
+pub const STORAGE_ERROR: i32 = -32000;
+pub const NOT_FOUND: i32 = -32001;
+pub const CONSTRAINT_VIOLATION: i32 = -32002;
+pub const VALUE_PARSE_ERROR: i32 = -32003;
+
+/// Errors returned by the function handlers. Each variant maps to a fixed JSON-RPC error code:
+///
+/// | Variant               | Code     | Data                                  |
+/// |-----------------------|----------|---------------------------------------|
+/// | `Storage`             | -32000   | `{"Cause": <SQLite message>}`         |
+/// | `NotFound`            | -32001   | `{"Kind": <object kind>, "Id": <id>}` |
+/// | `ConstraintViolation` | -32002   | depends on the violated constraint    |
+/// | `Parse`               | -32003   | `{"Value": <unparsable input>}`       |
+/// | `Validation`          | -32602   | none                                  |
+/// | `Internal`            | -32603   | none                                  |
 #[derive(Debug)]
-pub struct HandlingError {
-    pub message: String,
-    pub code: i32,
+pub enum HandlingError {
+    /// The database failed for a reason the client cannot fix.
+    Storage(SqlError),
+    /// The object referenced by the request does not exist.
+    NotFound { kind: &'static str, id: i64 },
+    /// The request would break a unique constraint, e.g. a duplicate name.
+    ConstraintViolation { message: String, data: Value },
+    /// A parameter value, e.g. a date, could not be parsed.
+    Parse { message: String, value: String },
+    /// The parameters are missing or inconsistent.
+    Validation(String),
+    /// The server is in a state in which it cannot handle requests.
+    Internal(String),
 }
 
-impl From<SqlError> for HandlingError {
-    fn from(_error: SqlError) -> Self {
-        HandlingError {
-            message: "Database error".to_string(),
-            code: 300,
+impl HandlingError {
+    pub fn code(&self) -> i32 {
+        match self {
+            HandlingError::Storage(_) => STORAGE_ERROR,
+            HandlingError::NotFound { .. } => NOT_FOUND,
+            HandlingError::ConstraintViolation { .. } => CONSTRAINT_VIOLATION,
+            HandlingError::Parse { .. } => VALUE_PARSE_ERROR,
+            HandlingError::Validation(_) => INVALID_PARAMS,
+            HandlingError::Internal(_) => INTERNAL_ERROR,
+        }
+    }
+
+    pub fn data(&self) -> Option<Value> {
+        match self {
+            HandlingError::Storage(err) => Some(json!({ "Cause": err.to_string() })),
+            HandlingError::NotFound { kind, id } => Some(json!({ "Kind": kind, "Id": id })),
+            HandlingError::ConstraintViolation { data, .. } => Some(data.clone()),
+            HandlingError::Parse { value, .. } => Some(json!({ "Value": value })),
+            HandlingError::Validation(_) | HandlingError::Internal(_) => None,
+        }
+    }
+
+    /// The error object of a response. The cause of storage errors goes to the data only.
+    pub fn to_json(&self) -> Value {
+        let message = match self {
+            HandlingError::Storage(_) => "Database error".to_string(),
+            _ => self.to_string(),
+        };
+        let mut error = json!({ "code": self.code(), "message": message });
+        if let Some(data) = self.data() {
+            error["data"] = data;
+        }
+        error
+    }
+}
+
+impl fmt::Display for HandlingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandlingError::Storage(err) => write!(f, "Database error: {}", err),
+            HandlingError::NotFound { kind, id } => write!(f, "No {} with id {} exists.", kind, id),
+            HandlingError::ConstraintViolation { message, .. }
+            | HandlingError::Parse { message, .. }
+            | HandlingError::Validation(message)
+            | HandlingError::Internal(message) => write!(f, "{}", message),
         }
     }
 }
+
+impl std::error::Error for HandlingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HandlingError::Storage(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<SqlError> for HandlingError {
+    fn from(error: SqlError) -> Self {
+        HandlingError::Storage(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storage_errors_keep_their_cause() {
+        let error = HandlingError::Storage(SqlError::InvalidQuery);
+        assert!(error.to_string().starts_with("Database error: "));
+        assert!(error.to_string().len() > "Database error: ".len());
+
+        let json = error.to_json();
+        assert_eq!(json["message"], "Database error");
+        assert_eq!(json["code"], STORAGE_ERROR);
+        assert!(json["data"]["Cause"].is_string());
+    }
+}
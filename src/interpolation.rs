@@ -0,0 +1,120 @@
+use crate::data_model::TimeSeriesEntry;
+use chrono::{DateTime, Duration, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The value of the last entry at or before the time point (step function).
+    Previous,
+    /// The value of the first entry at or after the time point.
+    Next,
+    /// The value of the entry closest to the time point. Ties go to the previous entry.
+    Nearest,
+    /// The straight line between the previous and the next entry.
+    Linear,
+}
+
+/// Computes the value at time_point from the entries next to it. previous must not be after and
+/// next must not be before time_point. Returns None if the entries needed are missing or, with
+/// max_gap set, too far apart.
+pub fn interpolate(
+    time_point: &DateTime<Utc>,
+    previous: Option<&TimeSeriesEntry>,
+    next: Option<&TimeSeriesEntry>,
+    interpolation: Interpolation,
+    max_gap: Option<Duration>,
+) -> Option<f64> {
+    let within_gap = |from: &DateTime<Utc>, to: &DateTime<Utc>| match max_gap {
+        Some(max_gap) => *to - *from <= max_gap,
+        None => true,
+    };
+
+    match interpolation {
+        Interpolation::Previous => previous
+            .filter(|previous| within_gap(&previous.time_point, time_point))
+            .map(|previous| previous.value),
+        Interpolation::Next => next
+            .filter(|next| within_gap(time_point, &next.time_point))
+            .map(|next| next.value),
+        Interpolation::Nearest => {
+            let nearest = match (previous, next) {
+                (Some(previous), Some(next)) => {
+                    if *time_point - previous.time_point <= next.time_point - *time_point {
+                        Some(previous)
+                    } else {
+                        Some(next)
+                    }
+                }
+                (previous, next) => previous.or(next),
+            }?;
+
+            let (from, to) = if nearest.time_point <= *time_point {
+                (&nearest.time_point, time_point)
+            } else {
+                (time_point, &nearest.time_point)
+            };
+            within_gap(from, to).then_some(nearest.value)
+        }
+        Interpolation::Linear => {
+            let (previous, next) = (previous?, next?);
+            if previous.time_point == next.time_point {
+                return Some(previous.value);
+            }
+            if !within_gap(&previous.time_point, &next.time_point) {
+                return None;
+            }
+
+            let span = next.time_point - previous.time_point;
+            let offset = *time_point - previous.time_point;
+            // Nanoseconds overflow beyond about 292 years, microseconds still cover such spans.
+            let (span, offset) = match (span.num_nanoseconds(), offset.num_nanoseconds()) {
+                (Some(span), Some(offset)) => (span as f64, offset as f64),
+                _ => (
+                    span.num_microseconds()? as f64,
+                    offset.num_microseconds()? as f64,
+                ),
+            };
+            Some(previous.value + (next.value - previous.value) * offset / span)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn entry(time_point: DateTime<Utc>, value: f64) -> TimeSeriesEntry {
+        TimeSeriesEntry { time_point, value }
+    }
+
+    #[test]
+    fn linear_resolves_nanoseconds() {
+        let origin = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let previous = entry(origin, 0.0);
+        let next = entry(origin + Duration::nanoseconds(4), 4.0);
+        let value = interpolate(
+            &(origin + Duration::nanoseconds(1)),
+            Some(&previous),
+            Some(&next),
+            Interpolation::Linear,
+            None,
+        );
+        assert_eq!(value, Some(1.0));
+    }
+
+    #[test]
+    fn linear_spans_centuries() {
+        let origin = Utc.timestamp_opt(0, 0).unwrap();
+        let previous = entry(origin, 0.0);
+        let next = entry(origin + Duration::days(400 * 365), 400.0);
+        let value = interpolate(
+            &(origin + Duration::days(200 * 365)),
+            Some(&previous),
+            Some(&next),
+            Interpolation::Linear,
+            None,
+        )
+        .unwrap();
+        assert!((value - 200.0).abs() < 0.01);
+    }
+}
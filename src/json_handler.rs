@@ -1,75 +1,938 @@
-use crate::dao::Dao;
-use crate::data_model::time_point_from_str;
-use crate::errors::HandlingError;
-use chrono::{DateTime, Utc};
+use crate::alignment::{grid_time_points, values_on_grid, Fill, Grid};
+use crate::cursor::Cursor;
+use crate::dao::{backup, lock, open_read_only, ConflictPolicy, Dao, DaoRef};
+use crate::data_model::{
+    time_point_from_str, time_point_to_string, Aggregate, AggregatedTimeSeries, BackupState, Page,
+    Plot, PlotFilter, PlotOrder, PlotSortKey, TextMatch, TimeRange, TimeSeries, TimeSeriesEntry,
+};
+use crate::downsampling::{downsample, Downsampling};
+use crate::errors::{HandlingError, INVALID_REQUEST, METHOD_NOT_FOUND, PARSE_ERROR};
+use crate::interpolation::{interpolate, Interpolation};
+use chrono::{DateTime, Duration, Utc};
 use serde_json::{json, Value};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use tokio::sync::Notify;
 use tungstenite::protocol::Message;
 
-pub trait FunctionHandler {
+pub trait FunctionHandler: Send + Sync {
     fn handle(&self, json: Value) -> Result<Value, HandlingError>;
 }
 
+fn optional_time_point(json: &Value) -> Result<Option<DateTime<Utc>>, HandlingError> {
+    json.as_str().map(time_point_from_str).transpose()
+}
+
+fn time_range(json: &Value) -> Result<TimeRange, HandlingError> {
+    TimeRange::new(
+        optional_time_point(&json["StartDate"])?,
+        optional_time_point(&json["EndDate"])?,
+        json["EndInclusive"].as_bool().unwrap_or_default(),
+    )
+}
+
+/// 100 years of 365.25 days in seconds. Much wider buckets overflow the bucket arithmetic of the
+/// queries.
+const MAX_BUCKET_WIDTH: i64 = 36_525 * 24 * 3600;
+
+/// The optional "Aggregation" parameter of the queries, e.g.
+/// {"BucketWidth": 3600, "Functions": ["Min", "Max"]} with the bucket width in seconds.
+struct Aggregation {
+    bucket_width: i64,
+    aggregates: Vec<Aggregate>,
+}
+
+impl Aggregation {
+    fn from_json(json: &Value) -> Result<Option<Self>, HandlingError> {
+        if json.is_null() {
+            return Ok(None);
+        }
+
+        let bucket_width = json["BucketWidth"]
+            .as_i64()
+            .filter(|width| *width > 0 && *width <= MAX_BUCKET_WIDTH)
+            .ok_or(HandlingError::Validation(
+                "BucketWidth must be a positive number of seconds, up to 100 years".to_string(),
+            ))?;
+
+        let aggregates = json["Functions"]
+            .as_array()
+            .filter(|functions| !functions.is_empty())
+            .ok_or(HandlingError::Validation(
+                "Functions must be a non-empty list".to_string(),
+            ))?
+            .iter()
+            .map(|function| {
+                function.try_into().map_err(|_| {
+                    HandlingError::Validation(format!("Unknown aggregate function {}", function))
+                })
+            })
+            .collect::<Result<Vec<Aggregate>, _>>()?;
+
+        Ok(Some(Self {
+            bucket_width,
+            aggregates,
+        }))
+    }
+
+    fn apply(
+        &self,
+        dao: &Dao,
+        time_series: TimeSeries,
+        range: &TimeRange,
+    ) -> Result<AggregatedTimeSeries, HandlingError> {
+        let buckets = dao.get_buckets_for_time_series(time_series.id, range, self.bucket_width)?;
+        Ok(AggregatedTimeSeries {
+            time_series,
+            bucket_width: self.bucket_width,
+            aggregates: self.aggregates.clone(),
+            buckets,
+        })
+    }
+}
+
+/// The optional "MaxPoints" and "Downsampling" parameters of the queries.
+fn downsampling(json: &Value) -> Result<Option<(usize, Downsampling)>, HandlingError> {
+    if json["MaxPoints"].is_null() {
+        return Ok(None);
+    }
+
+    let max_points = json["MaxPoints"]
+        .as_u64()
+        .filter(|max_points| *max_points >= 2)
+        .ok_or(HandlingError::Validation(
+            "MaxPoints must be a number of at least 2".to_string(),
+        ))?;
+
+    let downsampling = match json["Downsampling"].as_str() {
+        None | Some("Lttb") => Downsampling::Lttb,
+        Some("MinMax") => Downsampling::MinMax,
+        Some(_) => {
+            return Err(HandlingError::Validation(
+                "Downsampling must be one of Lttb or MinMax".to_string(),
+            ))
+        }
+    };
+
+    if !json["Aggregation"].is_null() {
+        return Err(HandlingError::Validation(
+            "MaxPoints cannot be combined with Aggregation".to_string(),
+        ));
+    }
+
+    Ok(Some((max_points as usize, downsampling)))
+}
+
+/// The optional "Limit" parameter of the paged queries.
+fn limit(json: &Value) -> Result<Option<usize>, HandlingError> {
+    if json["Limit"].is_null() {
+        return Ok(None);
+    }
+
+    json["Limit"]
+        .as_u64()
+        .filter(|limit| *limit > 0)
+        .map(|limit| Some(limit as usize))
+        .ok_or(HandlingError::Validation(
+            "Limit must be a positive number".to_string(),
+        ))
+}
+
+/// The optional "Cursor" parameter of the paged queries, as returned in "NextCursor".
+fn cursor(json: &Value, kind: &str, scope: i64) -> Result<Option<Cursor>, HandlingError> {
+    match &json["Cursor"] {
+        Value::Null => Ok(None),
+        Value::String(cursor) => Ok(Some(Cursor::decode(cursor, kind, scope)?)),
+        _ => Err(HandlingError::Validation("Invalid Cursor".to_string())),
+    }
+}
+
+fn required_id(json: &Value, key: &str) -> Result<i64, HandlingError> {
+    json[key]
+        .as_i64()
+        .ok_or(HandlingError::Validation(format!("{} missing", key)))
+}
+
+fn plot_filter(json: &Value) -> Result<PlotFilter, HandlingError> {
+    let text = |key: &str| match &json[key] {
+        Value::Null => Ok(None),
+        Value::String(text) => Ok(Some(text.clone())),
+        _ => Err(HandlingError::Validation(format!(
+            "Filter {} must be a string",
+            key
+        ))),
+    };
+    let text_match = match json["Match"].as_str() {
+        None if json["Match"].is_null() => TextMatch::Contains,
+        Some("Contains") => TextMatch::Contains,
+        Some("Glob") => TextMatch::Glob,
+        _ => {
+            return Err(HandlingError::Validation(
+                "Match must be Contains or Glob".to_string(),
+            ))
+        }
+    };
+
+    Ok(PlotFilter {
+        name: text("Name")?,
+        description: text("Description")?,
+        series_name: text("SeriesName")?,
+        series_unit: text("SeriesUnit")?,
+        text_match,
+    })
+}
+
+fn plot_order(json: &Value) -> Result<PlotOrder, HandlingError> {
+    let key = match &json["SortBy"] {
+        Value::Null => PlotSortKey::Id,
+        sort_by => PlotSortKey::try_from(sort_by).map_err(|_| {
+            HandlingError::Validation(
+                "SortBy must be Id, Name, SeriesCount, FirstTimePoint or LastTimePoint".to_string(),
+            )
+        })?,
+    };
+    Ok(PlotOrder {
+        key,
+        descending: json["Descending"].as_bool().unwrap_or_default(),
+    })
+}
+
 struct GetAllPlots {
-    dao: Arc<Dao>,
+    dao: DaoRef,
 }
 
 impl FunctionHandler for GetAllPlots {
-    fn handle(&self, _json: Value) -> Result<Value, HandlingError> {
-        let plots = self.dao.get_all_plots()?;
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let filter = plot_filter(&json["Filter"])?;
+        let order = plot_order(&json)?;
+        let limit = limit(&json)?;
+
+        // A cursor is only valid for the order it was created with.
+        let cursor_kind = format!(
+            "plots/{}/{}",
+            order.key.name(),
+            if order.descending { "desc" } else { "asc" }
+        );
+        let after = match cursor(&json, &cursor_kind, 0)? {
+            Some(cursor) => match serde_json::from_str::<Value>(&cursor.position) {
+                Ok(Value::Array(position))
+                    if position.len() == 2 && (position[0].is_string() || position[0].is_i64()) =>
+                {
+                    Some((
+                        position[0].clone(),
+                        position[1]
+                            .as_i64()
+                            .ok_or(HandlingError::Validation("Invalid Cursor".to_string()))?,
+                    ))
+                }
+                _ => return Err(HandlingError::Validation("Invalid Cursor".to_string())),
+            },
+            None => None,
+        };
+        let paged = limit.is_some() || after.is_some();
+
+        let (plots, has_more) =
+            lock(&self.dao).get_all_plots(&filter, &order, &Page { after, limit })?;
+        let next_cursor = match plots.last() {
+            Some(summary) if has_more => {
+                let position = json!([summary.sort_value(order.key), summary.plot.id]);
+                Some(Cursor::new(&cursor_kind, 0, position.to_string()).encode())
+            }
+            _ => None,
+        };
+
         let mut plots_json: Vec<Value> = vec![];
-        for plot in plots {
-            plots_json.push((&plot).into());
+        for summary in plots {
+            plots_json.push((&summary).into());
         }
 
-        Ok(plots_json.into())
+        // Without paging the plain list is returned, as before paging existed.
+        if !paged {
+            return Ok(plots_json.into());
+        }
+        Ok(json!({ "Plots": plots_json, "NextCursor": next_cursor }))
     }
 }
 
 struct GetPlot {
-    dao: Arc<Dao>,
+    dao: DaoRef,
 }
 
 impl FunctionHandler for GetPlot {
     fn handle(&self, json: Value) -> Result<Value, HandlingError> {
         if let Some(id) = json["Id"].as_i64() {
-            let without_data = match json["WithoutData"].as_bool() {
-                Some(val) => val,
-                None => false,
-            };
+            // A page would have to cover several time series at once.
+            if !json["Limit"].is_null() || !json["Cursor"].is_null() {
+                return Err(HandlingError::Validation(
+                    "GetPlot is not paged, page the entries with GetTimeSeries".to_string(),
+                ));
+            }
+            let without_data = json["WithoutData"].as_bool().unwrap_or_default();
 
             if without_data {
                 // Only get the plot and time series metadata but no entries for the timeseries.
-                let mut plot = self.dao.get_plot(id)?;
-                self.dao.get_time_series_for_plot(&mut plot)?;
+                let dao = lock(&self.dao);
+                let mut plot = dao.get_plot(id)?;
+                dao.get_time_series_for_plot(&mut plot)?;
                 return Ok((&plot).into());
+            } else if let Some(aggregation) = Aggregation::from_json(&json["Aggregation"])? {
+                let range = time_range(&json)?;
+                downsampling(&json)?;
+                let dao = lock(&self.dao);
+                let mut plot = dao.get_plot(id)?;
+                dao.get_time_series_for_plot(&mut plot)?;
+
+                let mut time_series_jsons: Vec<Value> = vec![];
+                for time_series in plot.time_series.drain(..) {
+                    let aggregated = aggregation.apply(&dao, time_series, &range)?;
+                    time_series_jsons.push((&aggregated).into());
+                }
+
+                let mut plot_json: Value = (&plot).into();
+                plot_json["TimeSeries"] = time_series_jsons.into();
+                return Ok(plot_json);
             } else {
-                // Get all entries if "StartDate" is not set.
-                let start_date = match json["StartDate"]
-                    .as_str()
-                    .map(|date_string| time_point_from_str(&date_string))
-                {
-                    None => Ok(None),
-                    Some(Ok(date_time)) => Ok(Some(date_time)),
-                    Some(Err(err)) => Err(err),
-                }?;
+                // Get all entries if neither "StartDate" nor "EndDate" is set.
+                let range = time_range(&json)?;
+                let downsampling = downsampling(&json)?;
 
-                let plot = self.dao.get_plot_with_data(id, start_date)?;
+                let mut plot = lock(&self.dao).get_plot_with_data(id, &range)?;
+                if let Some((max_points, downsampling)) = downsampling {
+                    for time_series in plot.time_series.iter_mut() {
+                        downsample(time_series, max_points, downsampling);
+                    }
+                }
                 return Ok((&plot).into());
             }
         }
 
-        Err(HandlingError {
-            message: "Id missing".to_string(),
-            code: 420,
+        Err(HandlingError::Validation("Id missing".to_string()))
+    }
+}
+
+struct GetTimeSeries {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for GetTimeSeries {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let id = required_id(&json, "Id")?;
+
+        if json["WithoutData"].as_bool().unwrap_or_default() {
+            let time_series = lock(&self.dao).get_time_series(id)?;
+            return Ok((&time_series).into());
+        }
+
+        let range = time_range(&json)?;
+        let downsampling = downsampling(&json)?;
+        // Buckets and downsampled points are computed over the whole range, not page by page.
+        let paging = !json["Limit"].is_null() || !json["Cursor"].is_null();
+        if paging && (!json["Aggregation"].is_null() || downsampling.is_some()) {
+            return Err(HandlingError::Validation(
+                "Limit and Cursor cannot be combined with Aggregation or MaxPoints".to_string(),
+            ));
+        }
+        if let Some(aggregation) = Aggregation::from_json(&json["Aggregation"])? {
+            let dao = lock(&self.dao);
+            let time_series = dao.get_time_series(id)?;
+            let aggregated = aggregation.apply(&dao, time_series, &range)?;
+            return Ok((&aggregated).into());
+        }
+
+        let limit = limit(&json)?;
+        let cursor = cursor(&json, "entries", id)?;
+        let after = cursor
+            .as_ref()
+            .map(|cursor| time_point_from_str(&cursor.position))
+            .transpose()?;
+        let paged = limit.is_some() || after.is_some();
+
+        let (mut time_series, has_more) =
+            lock(&self.dao).get_time_series_with_data(id, &range, &Page { after, limit })?;
+        let next_cursor = match time_series.time_points.last() {
+            Some(time_point) if has_more => {
+                Some(Cursor::new("entries", id, time_point_to_string(time_point)).encode())
+            }
+            _ => None,
+        };
+
+        if let Some((max_points, downsampling)) = downsampling {
+            downsample(&mut time_series, max_points, downsampling);
+        }
+
+        let mut time_series_json: Value = (&time_series).into();
+        if paged {
+            time_series_json["NextCursor"] = json!(next_cursor);
+        }
+        Ok(time_series_json)
+    }
+}
+
+struct GetLatest {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for GetLatest {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let count = match &json["Count"] {
+            Value::Null => 1,
+            count => count
+                .as_u64()
+                .filter(|count| *count > 0)
+                .ok_or(HandlingError::Validation(
+                    "Count must be a positive number".to_string(),
+                ))? as usize,
+        };
+
+        let dao = lock(&self.dao);
+        let mut time_series_list: Vec<TimeSeries> = vec![];
+        if let Some(ids) = json["TimeSeriesIds"].as_array() {
+            for id in ids {
+                let id = id.as_i64().ok_or(HandlingError::Validation(
+                    "TimeSeriesIds must be a list of ids".to_string(),
+                ))?;
+                time_series_list.push(dao.get_time_series(id)?);
+            }
+        } else if let Some(plot_id) = json["PlotId"].as_i64() {
+            let mut plot = dao.get_plot(plot_id)?;
+            dao.get_time_series_for_plot(&mut plot)?;
+            time_series_list = plot.time_series;
+        } else {
+            return Err(HandlingError::Validation(
+                "Either TimeSeriesIds or PlotId must be given".to_string(),
+            ));
+        }
+
+        let mut time_series_jsons: Vec<Value> = vec![];
+        for mut time_series in time_series_list {
+            dao.get_latest_entries_for_time_series(&mut time_series, count)?;
+            time_series_jsons.push((&time_series).into());
+        }
+        Ok(time_series_jsons.into())
+    }
+}
+
+fn interpolation(json: &Value) -> Result<Interpolation, HandlingError> {
+    match json["Interpolation"].as_str() {
+        None | Some("Previous") => Ok(Interpolation::Previous),
+        Some("Next") => Ok(Interpolation::Next),
+        Some("Nearest") => Ok(Interpolation::Nearest),
+        Some("Linear") => Ok(Interpolation::Linear),
+        Some(_) => Err(HandlingError::Validation(
+            "Interpolation must be one of Previous, Next, Nearest or Linear".to_string(),
+        )),
+    }
+}
+
+/// The optional "MaxGap" parameter in seconds.
+fn max_gap(json: &Value) -> Result<Option<Duration>, HandlingError> {
+    if json["MaxGap"].is_null() {
+        return Ok(None);
+    }
+
+    json["MaxGap"]
+        .as_f64()
+        .filter(|max_gap| *max_gap >= 0.0)
+        .map(|max_gap| Some(Duration::microseconds((max_gap * 1e6) as i64)))
+        .ok_or(HandlingError::Validation(
+            "MaxGap must be a non-negative number of seconds".to_string(),
+        ))
+}
+
+struct GetValuesAt {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for GetValuesAt {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let time_series_id = required_id(&json, "TimeSeriesId")?;
+        let time_points = json["TimePoints"]
+            .as_array()
+            .ok_or(HandlingError::Validation("TimePoints missing".to_string()))?
+            .iter()
+            .map(|time_point| time_point_from_str(time_point.as_str().unwrap_or_default()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let interpolation = interpolation(&json)?;
+        let max_gap = max_gap(&json)?;
+
+        let dao = lock(&self.dao);
+        // Fails with a not found error for unknown ids instead of returning only nulls.
+        dao.get_time_series(time_series_id)?;
+
+        let mut values: Vec<Option<f64>> = vec![];
+        for time_point in time_points.iter() {
+            let (previous, next) = dao.get_neighbouring_entries(time_series_id, time_point)?;
+            values.push(interpolate(
+                time_point,
+                previous.as_ref(),
+                next.as_ref(),
+                interpolation,
+                max_gap,
+            ));
+        }
+
+        Ok(json!({
+            "TimeSeriesId": time_series_id,
+            "TimePoints": time_points.iter().map(time_point_to_string).collect::<Vec<_>>(),
+            "Values": values
+        }))
+    }
+}
+
+struct GetAligned {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for GetAligned {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let ids = json["TimeSeriesIds"]
+            .as_array()
+            .filter(|ids| !ids.is_empty())
+            .ok_or(HandlingError::Validation(
+                "TimeSeriesIds must be a non-empty list of ids".to_string(),
+            ))?
+            .iter()
+            .map(|id| {
+                id.as_i64().ok_or(HandlingError::Validation(
+                    "TimeSeriesIds must be a non-empty list of ids".to_string(),
+                ))
+            })
+            .collect::<Result<Vec<i64>, _>>()?;
+
+        // Either "Union" or {"Interval": <seconds>}.
+        let grid = match &json["Grid"] {
+            Value::String(grid) if grid == "Union" => Grid::Union,
+            grid => grid["Interval"]
+                .as_i64()
+                .filter(|interval| *interval > 0)
+                .map(Grid::Regular)
+                .ok_or(HandlingError::Validation(
+                    "Grid must be \"Union\" or {\"Interval\": <seconds>}".to_string(),
+                ))?,
+        };
+
+        let fill = match json["Fill"].as_str() {
+            None | Some("None") => Fill::Exact,
+            Some("Previous") => Fill::Interpolated(Interpolation::Previous),
+            Some("Next") => Fill::Interpolated(Interpolation::Next),
+            Some("Nearest") => Fill::Interpolated(Interpolation::Nearest),
+            Some("Linear") => Fill::Interpolated(Interpolation::Linear),
+            Some(_) => {
+                return Err(HandlingError::Validation(
+                    "Fill must be one of None, Previous, Next, Nearest or Linear".to_string(),
+                ))
+            }
+        };
+        let max_gap = max_gap(&json)?;
+        let range = time_range(&json)?;
+
+        let dao = lock(&self.dao);
+        let mut time_series_list: Vec<TimeSeries> = vec![];
+        for id in ids {
+            time_series_list.push(dao.get_time_series_with_margin(id, &range)?);
+        }
+
+        let time_points = grid_time_points(grid, &time_series_list, &range)?;
+        let columns: Vec<Value> = time_series_list
+            .iter()
+            .map(|time_series| {
+                json!({
+                    "TimeSeriesId": time_series.id,
+                    "Name": time_series.name,
+                    "Unit": time_series.unit,
+                    "Values": values_on_grid(time_series, &time_points, fill, max_gap)
+                })
+            })
+            .collect();
+
+        Ok(json!({
+            "TimePoints": time_points.iter().map(time_point_to_string).collect::<Vec<_>>(),
+            "Columns": columns
+        }))
+    }
+}
+
+struct GetStatistics {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for GetStatistics {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let time_series_id = required_id(&json, "TimeSeriesId")?;
+        let range = time_range(&json)?;
+
+        let percentiles = match &json["Percentiles"] {
+            Value::Null => vec![25.0, 50.0, 75.0],
+            percentiles => percentiles
+                .as_array()
+                .and_then(|percentiles| {
+                    percentiles
+                        .iter()
+                        .map(|percentile| {
+                            percentile
+                                .as_f64()
+                                .filter(|percentile| (0.0..=100.0).contains(percentile))
+                        })
+                        .collect::<Option<Vec<f64>>>()
+                })
+                .ok_or(HandlingError::Validation(
+                    "Percentiles must be a list of numbers from 0 to 100".to_string(),
+                ))?,
+        };
+
+        let statistics = lock(&self.dao).get_statistics(time_series_id, &range, &percentiles)?;
+        Ok((&statistics).into())
+    }
+}
+
+struct AddPlot {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for AddPlot {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let plot: Plot = (&json["Plot"])
+            .try_into()
+            .map_err(|_| HandlingError::Validation("Plot missing or malformed".to_string()))?;
+
+        let plot = lock(&self.dao).add_plot(&plot)?;
+        Ok((&plot).into())
+    }
+}
+
+struct AddTimeSeries {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for AddTimeSeries {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let plot_id = required_id(&json, "PlotId")?;
+        let time_series: TimeSeries = (&json["TimeSeries"]).try_into().map_err(|_| {
+            HandlingError::Validation("TimeSeries missing or malformed".to_string())
+        })?;
+
+        let time_series = lock(&self.dao).add_time_series(plot_id, &time_series)?;
+        Ok(json!({ "Id": time_series.id }))
+    }
+}
+
+struct AppendEntries {
+    dao: DaoRef,
+}
+
+fn to_entry(json: &Value) -> Result<(i64, TimeSeriesEntry), HandlingError> {
+    let time_series_id = json["TimeSeriesId"].as_i64();
+    let time_point = json["TimePoint"].as_str();
+    let value = json["Value"].as_f64();
+
+    if let (Some(time_series_id), Some(time_point), Some(value)) =
+        (time_series_id, time_point, value)
+    {
+        Ok((
+            time_series_id,
+            TimeSeriesEntry {
+                time_point: time_point_from_str(time_point)?,
+                value,
+            },
+        ))
+    } else {
+        Err(HandlingError::Validation(
+            "Entries must consist of TimeSeriesId, TimePoint and Value".to_string(),
+        ))
+    }
+}
+
+impl FunctionHandler for AppendEntries {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let on_conflict = match json["OnConflict"].as_str() {
+            None | Some("Reject") => ConflictPolicy::Reject,
+            Some("Skip") => ConflictPolicy::Skip,
+            Some("Overwrite") => ConflictPolicy::Overwrite,
+            Some(_) => {
+                return Err(HandlingError::Validation(
+                    "OnConflict must be one of Reject, Skip or Overwrite".to_string(),
+                ))
+            }
+        };
+
+        let entries = json["Entries"]
+            .as_array()
+            .ok_or(HandlingError::Validation("Entries missing".to_string()))?
+            .iter()
+            .map(to_entry)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let written = lock(&self.dao).append_entries(&entries, on_conflict)?;
+        Ok(json!({ "Written": written }))
+    }
+}
+
+struct UpdatePlot {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for UpdatePlot {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let id = required_id(&json, "Id")?;
+        let plot =
+            lock(&self.dao).update_plot(id, json["Name"].as_str(), json["Description"].as_str())?;
+        Ok((&plot).into())
+    }
+}
+
+struct UpdateTimeSeries {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for UpdateTimeSeries {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let id = required_id(&json, "Id")?;
+        let time_series =
+            lock(&self.dao).update_time_series(id, json["Name"].as_str(), json["Unit"].as_str())?;
+        Ok((&time_series).into())
+    }
+}
+
+struct DeletePlot {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for DeletePlot {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let id = required_id(&json, "Id")?;
+        let deleted = lock(&self.dao).delete_plot(id)?;
+        Ok((&deleted).into())
+    }
+}
+
+struct DeleteTimeSeries {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for DeleteTimeSeries {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let id = required_id(&json, "Id")?;
+        let deleted = lock(&self.dao).delete_time_series(id)?;
+        Ok((&deleted).into())
+    }
+}
+
+struct DeleteEntries {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for DeleteEntries {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let time_series_id = required_id(&json, "TimeSeriesId")?;
+        let range = time_range(&json)?;
+        // A misspelled bound must not wipe the whole series.
+        let all = json["All"].as_bool().unwrap_or_default();
+        if range.start.is_none() && range.end.is_none() && !all {
+            return Err(HandlingError::Validation(
+                "StartDate or EndDate missing, set All to true to delete all entries".to_string(),
+            ));
+        }
+
+        let deleted = lock(&self.dao).delete_entries(time_series_id, &range)?;
+        Ok((&deleted).into())
+    }
+}
+
+// Seconds, or null to keep entries forever. The key must be given, so that null is never implied.
+fn retention(json: &Value) -> Result<Option<i64>, HandlingError> {
+    match json.get("Retention") {
+        None => Err(HandlingError::Validation("Retention missing".to_string())),
+        Some(Value::Null) => Ok(None),
+        Some(retention) => retention
+            .as_i64()
+            .filter(|retention| *retention > 0)
+            .map(Some)
+            .ok_or(HandlingError::Validation(
+                "Retention must be a positive number of seconds or null".to_string(),
+            )),
+    }
+}
+
+struct SetRetention {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for SetRetention {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let retention = retention(&json)?;
+        let mut dao = lock(&self.dao);
+
+        let plot_retention = match (json["PlotId"].as_i64(), json["TimeSeriesId"].as_i64()) {
+            (Some(plot_id), None) => dao.set_plot_retention(plot_id, retention)?,
+            (None, Some(time_series_id)) => {
+                dao.set_time_series_retention(time_series_id, retention)?
+            }
+            _ => {
+                return Err(HandlingError::Validation(
+                    "Either PlotId or TimeSeriesId required".to_string(),
+                ))
+            }
+        };
+        Ok((&plot_retention).into())
+    }
+}
+
+struct GetRetention {
+    dao: DaoRef,
+}
+
+impl FunctionHandler for GetRetention {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let plot_id = required_id(&json, "PlotId")?;
+        let plot_retention = lock(&self.dao).get_retention(plot_id)?;
+        Ok((&plot_retention).into())
+    }
+}
+
+/// Purges right away instead of waiting for the next scheduled purge. The purge runs in the
+/// background in chunks, like the scheduled ones, so the response does not wait for it; its
+/// results show in the PurgedEntries and LastPurge of GetRetention.
+struct Purge {
+    requests: Arc<Notify>,
+}
+
+impl FunctionHandler for Purge {
+    fn handle(&self, _json: Value) -> Result<Value, HandlingError> {
+        self.requests.notify_one();
+        Ok(json!({ "Scheduled": true }))
+    }
+}
+
+/// The backups the server started, by the file name the client gave them.
+type BackupStates = Arc<Mutex<HashMap<String, BackupState>>>;
+
+fn lock_backup_states(states: &BackupStates) -> MutexGuard<'_, HashMap<String, BackupState>> {
+    states.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Clients may only name a file within the directory the operator chose.
+fn backup_file_name(json: &Value) -> Result<String, HandlingError> {
+    json["Path"]
+        .as_str()
+        .filter(|path| {
+            let mut components = Path::new(path).components();
+            matches!(
+                (components.next(), components.next()),
+                (Some(Component::Normal(_)), None)
+            )
         })
+        .map(str::to_string)
+        .ok_or(HandlingError::Validation(
+            "Path must be a file name within the backup directory".to_string(),
+        ))
+}
+
+fn backup_state_value(file_name: &str, state: &BackupState) -> Value {
+    let mut value: Value = state.into();
+    value["Path"] = json!(file_name);
+    value
+}
+
+/// Starts a backup and returns right away. The copy reads the database file with a connection and
+/// a thread of its own, so the server keeps handling requests meanwhile. GetBackup reports its
+/// progress and finally the size and checksum of the file.
+struct Backup {
+    dao: DaoRef,
+    directory: Option<PathBuf>,
+    states: BackupStates,
+}
+
+impl FunctionHandler for Backup {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let directory = self.directory.as_ref().ok_or(HandlingError::Validation(
+            "Backups are disabled, the server has no backup directory".to_string(),
+        ))?;
+        let file_name = backup_file_name(&json)?;
+        let source =
+            lock(&self.dao)
+                .path()
+                .map(Path::to_path_buf)
+                .ok_or(HandlingError::Validation(
+                    "Backups need a database file, the server keeps its data in memory only"
+                        .to_string(),
+                ))?;
+
+        let path = directory.join(&file_name);
+        let state = BackupState::Running {
+            copied: 0,
+            total: 0,
+        };
+        {
+            let mut states = lock_backup_states(&self.states);
+            if let Some(BackupState::Running { .. }) = states.get(&file_name) {
+                return Err(HandlingError::Validation(format!(
+                    "A backup to {} is already running.",
+                    file_name
+                )));
+            }
+            if path.exists() {
+                return Err(HandlingError::Validation(format!(
+                    "Backup target {} already exists.",
+                    file_name
+                )));
+            }
+            states.insert(file_name.clone(), state.clone());
+        }
+
+        let states = self.states.clone();
+        let key = file_name.clone();
+        thread::spawn(move || {
+            let result = open_read_only(&source).and_then(|conn| {
+                backup(&conn, &path, |copied, total| {
+                    lock_backup_states(&states)
+                        .insert(key.clone(), BackupState::Running { copied, total });
+                })
+            });
+            let state = match result {
+                Ok(snapshot) => {
+                    println!("Backup written to {}", snapshot.path);
+                    BackupState::Done(snapshot)
+                }
+                Err(err) => {
+                    eprintln!("Backup to {} failed: {}", path.display(), err);
+                    BackupState::Failed(err.to_string())
+                }
+            };
+            lock_backup_states(&states).insert(key, state);
+        });
+        Ok(backup_state_value(&file_name, &state))
+    }
+}
+
+struct GetBackup {
+    states: BackupStates,
+}
+
+impl FunctionHandler for GetBackup {
+    fn handle(&self, json: Value) -> Result<Value, HandlingError> {
+        let file_name = backup_file_name(&json)?;
+        let states = lock_backup_states(&self.states);
+        let state = states.get(&file_name).ok_or_else(|| {
+            HandlingError::Validation(format!("No backup to {} was started.", file_name))
+        })?;
+        Ok(backup_state_value(&file_name, state))
     }
 }
 
 type Handler = Arc<dyn FunctionHandler>;
 
-fn get_handler_map(dao: Arc<Dao>) -> HashMap<String, Handler> {
+fn error_response(id: Value, code: i32, message: &str) -> Value {
+    json![{"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}}]
+}
+
+fn get_handler_map(
+    dao: DaoRef,
+    backup_directory: Option<PathBuf>,
+    purge_requests: Arc<Notify>,
+) -> HashMap<String, Handler> {
+    let backup_states = BackupStates::default();
     HashMap::from([
         (
             "GetAllPlots".to_string(),
@@ -79,6 +942,86 @@ fn get_handler_map(dao: Arc<Dao>) -> HashMap<String, Handler> {
             "GetPlot".to_string(),
             Arc::new(GetPlot { dao: dao.clone() }) as Handler,
         ),
+        (
+            "GetTimeSeries".to_string(),
+            Arc::new(GetTimeSeries { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "GetLatest".to_string(),
+            Arc::new(GetLatest { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "GetValuesAt".to_string(),
+            Arc::new(GetValuesAt { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "GetAligned".to_string(),
+            Arc::new(GetAligned { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "GetStatistics".to_string(),
+            Arc::new(GetStatistics { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "AddPlot".to_string(),
+            Arc::new(AddPlot { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "AddTimeSeries".to_string(),
+            Arc::new(AddTimeSeries { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "AppendEntries".to_string(),
+            Arc::new(AppendEntries { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "UpdatePlot".to_string(),
+            Arc::new(UpdatePlot { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "UpdateTimeSeries".to_string(),
+            Arc::new(UpdateTimeSeries { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "DeletePlot".to_string(),
+            Arc::new(DeletePlot { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "DeleteTimeSeries".to_string(),
+            Arc::new(DeleteTimeSeries { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "DeleteEntries".to_string(),
+            Arc::new(DeleteEntries { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "SetRetention".to_string(),
+            Arc::new(SetRetention { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "GetRetention".to_string(),
+            Arc::new(GetRetention { dao: dao.clone() }) as Handler,
+        ),
+        (
+            "Purge".to_string(),
+            Arc::new(Purge {
+                requests: purge_requests,
+            }) as Handler,
+        ),
+        (
+            "Backup".to_string(),
+            Arc::new(Backup {
+                dao: dao.clone(),
+                directory: backup_directory,
+                states: backup_states.clone(),
+            }) as Handler,
+        ),
+        (
+            "GetBackup".to_string(),
+            Arc::new(GetBackup {
+                states: backup_states,
+            }) as Handler,
+        ),
     ])
 }
 
@@ -87,39 +1030,346 @@ pub struct Dispatcher {
 }
 
 impl Dispatcher {
-    pub fn new(dao: Dao) -> Self {
+    /// Backup is only available with a backup_directory, into which the snapshots are written.
+    /// Purge requests are passed on to the task that purges, through purge_requests.
+    pub fn new(
+        dao: DaoRef,
+        backup_directory: Option<PathBuf>,
+        purge_requests: Arc<Notify>,
+    ) -> Self {
         Self {
-            handler: get_handler_map(Arc::new(dao)),
+            handler: get_handler_map(dao, backup_directory, purge_requests),
         }
     }
 
     pub fn dispatch(&self, message: &Message) -> Option<Message> {
-        if let Ok(msg_string) = message.to_text() {
-            if let Ok(json_value) = serde_json::from_str(msg_string) {
-                return self.dispatch_internal(json_value);
-            } else {
-                println!("Could not parse JSON.");
+        // Control frames are handled by tungstenite itself.
+        if !message.is_text() && !message.is_binary() {
+            return None;
+        }
+
+        let response = match message
+            .to_text()
+            .ok()
+            .and_then(|msg_string| serde_json::from_str(msg_string).ok())
+        {
+            Some(Value::Array(batch)) => self.dispatch_batch(batch),
+            Some(json_value) => self.dispatch_internal(json_value),
+            None => Some(error_response(Value::Null, PARSE_ERROR, "Parse error")),
+        };
+        response.map(|response| Message::text(response.to_string()))
+    }
+
+    fn dispatch_batch(&self, batch: Vec<Value>) -> Option<Value> {
+        if batch.is_empty() {
+            return Some(error_response(
+                Value::Null,
+                INVALID_REQUEST,
+                "Batch must not be empty",
+            ));
+        }
+
+        let responses: Vec<Value> = batch
+            .into_iter()
+            .filter_map(|json_rpc| self.dispatch_internal(json_rpc))
+            .collect();
+
+        // A batch consisting only of notifications is not answered at all.
+        if responses.is_empty() {
+            None
+        } else {
+            Some(Value::Array(responses))
+        }
+    }
+
+    fn dispatch_internal(&self, json_rpc: Value) -> Option<Value> {
+        let mut request = match json_rpc {
+            Value::Object(request) => request,
+            _ => {
+                return Some(error_response(
+                    Value::Null,
+                    INVALID_REQUEST,
+                    "Request must be an object",
+                ))
             }
+        };
+
+        // A request without an id is a notification, which is executed but must not be answered.
+        let is_notification = !request.contains_key("id");
+        let id = request.remove("id").unwrap_or(Value::Null);
+        if !(id.is_null() || id.is_string() || id.is_number()) {
+            return Some(error_response(
+                Value::Null,
+                INVALID_REQUEST,
+                "id must be a string, a number or null",
+            ));
+        }
+
+        if request.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+            return Some(error_response(
+                id,
+                INVALID_REQUEST,
+                "jsonrpc must be \"2.0\"",
+            ));
+        }
+
+        let method = match request.get("method").and_then(Value::as_str) {
+            Some(method) => method.to_string(),
+            None => return Some(error_response(id, INVALID_REQUEST, "method missing")),
+        };
+
+        let params = request.remove("params").unwrap_or(Value::Null);
+        if !(params.is_null() || params.is_object() || params.is_array()) {
+            return Some(error_response(
+                id,
+                INVALID_REQUEST,
+                "params must be an object or an array",
+            ));
+        }
+
+        let handler = match self.handler.get(&method) {
+            Some(handler) => handler,
+            None if is_notification => {
+                eprintln!("Notification for unknown method {} dropped.", method);
+                return None;
+            }
+            None => {
+                return Some(error_response(
+                    id,
+                    METHOD_NOT_FOUND,
+                    &format!("Method {} not found", method),
+                ))
+            }
+        };
+
+        let response = handler.handle(params);
+
+        // Notifications are never answered, so failures can only be logged.
+        if is_notification {
+            if let Err(err) = response {
+                eprintln!("Notification {} failed: {} ({})", method, err, err.code());
+            }
+            return None;
+        }
+
+        match response {
+            Ok(result) => Some(json![{"jsonrpc": "2.0", "id": id, "result": result}]),
+            Err(err) => Some(json![{"jsonrpc": "2.0", "id": id, "error": err.to_json()}]),
         }
-        None
     }
+}
 
-    fn dispatch_internal(&self, mut json_rpc: Value) -> Option<Message> {
-        let id = json_rpc["id"].take();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
 
-        if !id.is_null() {
-            if let Some(method) = json_rpc["method"].take().as_str() {
-                let response = self.handler[method].handle(json_rpc["params"].take());
+    #[test]
+    fn bucket_width_is_capped() {
+        let aggregation = |width: i64| {
+            Aggregation::from_json(&json!({ "BucketWidth": width, "Functions": ["Count"] }))
+        };
+        assert!(aggregation(MAX_BUCKET_WIDTH).is_ok());
+        assert!(matches!(
+            aggregation(MAX_BUCKET_WIDTH + 1),
+            Err(HandlingError::Validation(_))
+        ));
+        assert!(matches!(
+            aggregation(i64::MAX),
+            Err(HandlingError::Validation(_))
+        ));
+    }
 
-                let response = match response {
-                    Ok(result) => json![{"jsonrpc": "2.0", "id": id, "result": result}],
-                    Err(err) => {
-                        json![{"jsonrpc": "2.0", "id": id, "error": {"message": err.message, "code": err.code}}]
-                    }
-                }.to_string();
-                return Some(Message::text(response));
+    #[test]
+    fn pages_continue_without_gaps_or_duplicates() {
+        let mut dao = Dao::new_in_memory().unwrap();
+        // Time points apart by fractions of a second, which the cursor has to keep exactly.
+        let origin = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let plot = dao
+            .add_plot(&Plot {
+                id: 0,
+                name: "plot".to_string(),
+                description: "".to_string(),
+                time_series: vec![TimeSeries {
+                    id: 0,
+                    plot_id: 0,
+                    name: "series".to_string(),
+                    unit: "".to_string(),
+                    time_points: (0..10)
+                        .map(|index| origin + Duration::nanoseconds(index * 1_500_000_001))
+                        .collect(),
+                    values: (0..10).map(f64::from).collect(),
+                }],
+            })
+            .unwrap();
+        let id = plot.time_series[0].id;
+        let handler = GetTimeSeries {
+            dao: Arc::new(Mutex::new(dao)),
+        };
+
+        let mut values: Vec<f64> = vec![];
+        let mut cursor = Value::Null;
+        loop {
+            let page = handler
+                .handle(json!({ "Id": id, "Limit": 3, "Cursor": cursor }))
+                .unwrap();
+            let page_values = page["Values"].as_array().unwrap();
+            assert!(page_values.len() <= 3);
+            values.extend(page_values.iter().map(|value| value.as_f64().unwrap()));
+            cursor = page["NextCursor"].clone();
+            if cursor.is_null() {
+                break;
             }
         }
-        None
+        assert_eq!(values, (0..10).map(f64::from).collect::<Vec<f64>>());
+    }
+
+    #[test]
+    fn paging_is_not_combined_with_aggregation_or_downsampling() {
+        let handler = GetTimeSeries {
+            dao: Arc::new(Mutex::new(Dao::new_in_memory().unwrap())),
+        };
+        for json in [
+            json!({ "Id": 1, "Limit": 3, "MaxPoints": 10 }),
+            json!({
+                "Id": 1,
+                "Cursor": "00",
+                "Aggregation": { "BucketWidth": 60, "Functions": ["Min"] }
+            }),
+        ] {
+            assert!(matches!(
+                handler.handle(json),
+                Err(HandlingError::Validation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn delete_entries_needs_a_bound_or_all() {
+        let mut dao = Dao::new_in_memory().unwrap();
+        let plot = dao
+            .add_plot(&Plot {
+                id: 0,
+                name: "plot".to_string(),
+                description: "".to_string(),
+                time_series: vec![TimeSeries {
+                    id: 0,
+                    plot_id: 0,
+                    name: "series".to_string(),
+                    unit: "".to_string(),
+                    time_points: vec![Utc.timestamp_opt(1_600_000_000, 0).unwrap()],
+                    values: vec![1.0],
+                }],
+            })
+            .unwrap();
+        let id = plot.time_series[0].id;
+        let handler = DeleteEntries {
+            dao: Arc::new(Mutex::new(dao)),
+        };
+
+        let misspelled =
+            handler.handle(json!({ "TimeSeriesId": id, "StarDate": "2020-01-01T00:00:00Z" }));
+        assert!(matches!(misspelled, Err(HandlingError::Validation(_))));
+        let deleted = handler
+            .handle(json!({ "TimeSeriesId": id, "All": true }))
+            .unwrap();
+        assert_eq!(deleted["DeletedEntries"], 1);
+    }
+
+    fn dispatcher() -> Dispatcher {
+        Dispatcher::new(
+            Arc::new(Mutex::new(Dao::new_in_memory().unwrap())),
+            None,
+            Arc::new(Notify::new()),
+        )
+    }
+
+    fn dispatch(dispatcher: &Dispatcher, request: &str) -> Option<Value> {
+        dispatcher
+            .dispatch(&Message::text(request))
+            .map(|response| serde_json::from_str(response.to_text().unwrap()).unwrap())
+    }
+
+    #[test]
+    fn malformed_requests_get_errors() {
+        let dispatcher = dispatcher();
+        for (request, code) in [
+            (r#"{"jsonrpc": "2.0", "id": 1"#, PARSE_ERROR),
+            (
+                r#"{"jsonrpc": "1.0", "id": 1, "method": "GetAllPlots"}"#,
+                INVALID_REQUEST,
+            ),
+            (r#"{"jsonrpc": "2.0", "id": 1}"#, INVALID_REQUEST),
+            (
+                r#"{"jsonrpc": "2.0", "id": 1, "method": "Unknown"}"#,
+                METHOD_NOT_FOUND,
+            ),
+        ] {
+            let response = dispatch(&dispatcher, request).unwrap();
+            assert_eq!(response["error"]["code"], code, "{}", request);
+            assert!(response.get("result").is_none());
+        }
+
+        let response = dispatch(
+            &dispatcher,
+            r#"{"jsonrpc": "2.0", "id": 7, "method": "Unknown"}"#,
+        )
+        .unwrap();
+        assert_eq!(response["id"], 7);
+    }
+
+    #[test]
+    fn batches_are_answered_per_request() {
+        let dispatcher = dispatcher();
+        let response = dispatch(&dispatcher, "[]").unwrap();
+        assert_eq!(response["error"]["code"], INVALID_REQUEST);
+
+        let responses = dispatch(
+            &dispatcher,
+            r#"[
+                {"jsonrpc": "2.0", "id": 1, "method": "GetAllPlots"},
+                {"jsonrpc": "2.0", "method": "GetAllPlots"},
+                {"jsonrpc": "2.0", "id": 2, "method": "Unknown"},
+                5
+            ]"#,
+        )
+        .unwrap();
+        let responses = responses.as_array().unwrap();
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["id"], 1);
+        assert!(responses[0].get("result").is_some());
+        assert_eq!(responses[1]["id"], 2);
+        assert_eq!(responses[1]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(responses[2]["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[test]
+    fn notifications_are_executed_but_not_answered() {
+        let dispatcher = dispatcher();
+        let add_plot = |name: &str| {
+            json!({
+                "jsonrpc": "2.0",
+                "method": "AddPlot",
+                "params": { "Plot": { "Name": name, "Description": "", "TimeSeries": [] } }
+            })
+        };
+
+        assert!(dispatch(&dispatcher, &add_plot("first").to_string()).is_none());
+        let batch =
+            json!([add_plot("second"), add_plot("first"), {"jsonrpc": "2.0", "method": "Unknown"}]);
+        assert!(dispatch(&dispatcher, &batch.to_string()).is_none());
+
+        let plots = dispatch(
+            &dispatcher,
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "GetAllPlots"}"#,
+        )
+        .unwrap();
+        let names: Vec<&str> = plots["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|plot| plot["Name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["first", "second"]);
     }
 }
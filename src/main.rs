@@ -16,26 +16,46 @@
 //! two, seeing the messages from the other client as they're received. For all
 //! connected clients they'll all join the same room and see everyone else's
 //! messages.
+mod alignment;
+mod bench;
+mod cursor;
 mod dao;
 mod data_model;
+mod downsampling;
 mod errors;
+mod interpolation;
 mod json_handler;
+mod migrations;
 
+use chrono::{DateTime, Utc};
 use futures_channel::mpsc::unbounded;
 use futures_util::{future, pin_mut, stream::TryStreamExt, StreamExt};
-use std::{env, io::Error as IoError, io::ErrorKind, net::SocketAddr, sync::Arc};
+use std::{
+    env,
+    io::Error as IoError,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use tokio::net::{TcpListener, TcpStream};
-use tokio::task;
+use tokio::sync::Notify;
+use tokio::{task, time};
 
-use dao::Dao;
-use data_model::Plot;
+use dao::{lock, Dao, DaoRef, PURGE_CHUNK_ENTRIES};
+use data_model::{Plot, PurgeReport};
+use errors::HandlingError;
 use json_handler::Dispatcher;
 
-use nix;
-
 type Dp = Arc<Dispatcher>;
 
+/// Seconds between two purges of entries beyond their retention.
+const DEFAULT_PURGE_INTERVAL: u64 = 3600;
+
+/// Pause between two chunks of a purge, in which requests waiting for the DAO are handled.
+const PURGE_CHUNK_PAUSE: Duration = Duration::from_millis(1);
+
 pub fn privdrop(user: &str, group: &str) -> Result<(), nix::Error> {
     match nix::unistd::Group::from_name(group)? {
         Some(group) => nix::unistd::setgid(group.gid),
@@ -77,37 +97,231 @@ async fn handle_connection(dispatcher: Dp, raw_stream: TcpStream, addr: SocketAd
     println!("{} disconnected", &addr);
 }
 
+/// Deletes the entries beyond the retention of their time series, a chunk per lock of the DAO so
+/// that requests are handled in between, and gives part of the space freed back to the file
+/// system.
+async fn purge(dao: &DaoRef, now: &DateTime<Utc>) -> Result<PurgeReport, HandlingError> {
+    let expiring = lock(dao).expiring_time_series(now)?;
+    let mut time_series = vec![];
+    for (id, cutoff) in expiring {
+        let mut deleted = 0;
+        loop {
+            let chunk = lock(dao).purge_chunk(id, cutoff, now)?;
+            deleted += chunk;
+            if chunk < PURGE_CHUNK_ENTRIES {
+                break;
+            }
+            // A mere yield would run the purge again right away, before the runtime polls
+            // the connections; a timer lets it do that first.
+            time::sleep(PURGE_CHUNK_PAUSE).await;
+        }
+        time_series.push((id, deleted));
+    }
+    let reclaimed_pages = lock(dao).reclaim_free_pages()?;
+    Ok(PurgeReport {
+        time_series,
+        reclaimed_pages,
+    })
+}
+
+/// Purges every interval, the first time right after startup, and whenever a client requests
+/// it.
+async fn purge_periodically(dao: DaoRef, interval: Duration, requests: Arc<Notify>) {
+    let mut ticks = time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticks.tick() => {}
+            _ = requests.notified() => {}
+        }
+        let report = purge(&dao, &Utc::now()).await;
+        match report {
+            Ok(report) if report.deleted_entries() > 0 => println!(
+                "Purged {} entries from {} time series, reclaimed {} pages.",
+                report.deleted_entries(),
+                report
+                    .time_series
+                    .iter()
+                    .filter(|(_, entries)| *entries > 0)
+                    .count(),
+                report.reclaimed_pages
+            ),
+            Ok(_) => {}
+            Err(err) => eprintln!("Purge failed: {}", err),
+        }
+    }
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), IoError> {
-    let mut dao =
-        Dao::new_in_memory().or(Err(IoError::new(ErrorKind::Other, "Database error.")))?;
-    dao.add_plot(&Plot {
-        id: 0,
-        name: "entry".to_string(),
-        description: "desc".to_string(),
-        time_series: vec![],
-    })
-    .or(Err(IoError::new(ErrorKind::Other, "Database error.")))?;
-    let dispatcher = Arc::new(Dispatcher::new(dao));
-    let addr = env::args()
-        .nth(1)
+    // Options may appear anywhere, the remaining arguments are positional.
+    let mut database: Option<String> = None;
+    let mut backup_dir: Option<String> = None;
+    let mut restore: Option<String> = None;
+    let mut purge_interval: Option<String> = None;
+    let mut force_restore = false;
+    let mut positional: Vec<String> = vec![];
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let option = match arg.as_str() {
+            // --restore refuses to replace a database with data, as the flag could stay in a
+            // service configuration and restore the backup at every start.
+            "--force-restore" => {
+                force_restore = true;
+                continue;
+            }
+            "--database" => &mut database,
+            "--backup-dir" => &mut backup_dir,
+            "--restore" => &mut restore,
+            "--purge-interval" => &mut purge_interval,
+            _ => {
+                positional.push(arg);
+                continue;
+            }
+        };
+        *option = Some(
+            args.next()
+                .ok_or_else(|| IoError::other(format!("{} requires a value.", arg)))?,
+        );
+    }
+
+    // "bench [entries]" compares the throughput of the entry table layouts and the statement cache.
+    if positional.first().map(String::as_str) == Some("bench") {
+        let entries = match positional.get(1) {
+            Some(entries) => entries
+                .parse::<usize>()
+                .map_err(|_| IoError::other("bench expects a number of entries."))?,
+            None => 200_000,
+        };
+        return bench::run(entries)
+            .map_err(|err| IoError::other(format!("Benchmark failed: {}", err)));
+    }
+
+    // "migrate" only brings the database schema up to date and converts it to incremental vacuum,
+    // without starting the server.
+    if positional.first().map(String::as_str) == Some("migrate") {
+        let path = database.ok_or_else(|| IoError::other("migrate requires --database."))?;
+        let converted = Dao::open(&path)
+            .and_then(|dao| dao.enable_incremental_vacuum())
+            .map_err(|err| {
+                IoError::other(format!("Could not migrate database {}: {}", path, err))
+            })?;
+        if converted {
+            println!("Converted database {} to incremental vacuum.", path);
+        }
+        println!(
+            "Database {} is at schema version {}.",
+            path,
+            migrations::latest_version()
+        );
+        return Ok(());
+    }
+
+    // "backup <target>" writes a snapshot of the database, which may be in use by a server.
+    if positional.first().map(String::as_str) == Some("backup") {
+        let path = database.ok_or_else(|| IoError::other("backup requires --database."))?;
+        let target = positional
+            .get(1)
+            .ok_or_else(|| IoError::other("backup requires a target path."))?;
+        // Read only, so that a database in use is neither migrated nor vacuumed underneath its
+        // server.
+        let snapshot = dao::open_read_only(&path)
+            .and_then(|source| {
+                dao::backup(&source, target, |copied, total| {
+                    println!("{}/{} pages copied", copied, total)
+                })
+            })
+            .map_err(|err| IoError::other(format!("Backup of {} failed: {}", path, err)))?;
+        println!(
+            "Wrote {} ({} bytes, SHA-1 {}).",
+            snapshot.path, snapshot.size, snapshot.sha1
+        );
+        return Ok(());
+    }
+
+    let dao = match (&database, &restore) {
+        (Some(path), Some(backup)) => {
+            let dao = Dao::restore(path, backup, force_restore, |copied, total| {
+                println!("Restoring {}: {}/{} pages", backup, copied, total)
+            })
+            .map_err(|err| {
+                IoError::other(format!(
+                    "Could not restore {} from {}: {}",
+                    path, backup, err
+                ))
+            })?;
+            println!("Using database {} restored from {}", path, backup);
+            dao
+        }
+        (None, Some(_)) => return Err(IoError::other("--restore requires --database.")),
+        (Some(path), None) => {
+            let dao = Dao::open(path).map_err(|err| {
+                IoError::other(format!("Could not open database {}: {}", path, err))
+            })?;
+            println!("Using database {}", path);
+            dao
+        }
+        (None, None) => {
+            println!("No database specified, data is kept in memory only.");
+            let mut dao = Dao::new_in_memory().or(Err(IoError::other("Database error.")))?;
+            dao.add_plot(&Plot {
+                id: 0,
+                name: "entry".to_string(),
+                description: "desc".to_string(),
+                time_series: vec![],
+            })
+            .or(Err(IoError::other("Database error.")))?;
+            dao
+        }
+    };
+    // Converting takes a full vacuum, which is left to migrate. A database in memory has no file
+    // to give space back to.
+    if dao.path().is_some() && !dao.has_incremental_vacuum().unwrap_or(true) {
+        println!(
+            "Purges cannot give space back to the file system, run migrate to convert the \
+            database to incremental vacuum."
+        );
+    }
+    let purge_interval = match purge_interval {
+        Some(seconds) => seconds
+            .parse::<u64>()
+            .ok()
+            .filter(|seconds| *seconds > 0)
+            .ok_or_else(|| IoError::other("--purge-interval expects a number of seconds."))?,
+        None => DEFAULT_PURGE_INTERVAL,
+    };
+    let dao: DaoRef = Arc::new(Mutex::new(dao));
+    let purge_requests = Arc::new(Notify::new());
+    let dispatcher = Arc::new(Dispatcher::new(
+        dao.clone(),
+        backup_dir.map(PathBuf::from),
+        purge_requests.clone(),
+    ));
+    let mut positional = positional.into_iter();
+    let addr = positional
+        .next()
         .unwrap_or_else(|| "127.0.0.1:8080".to_string());
-    let user = env::args().nth(2);
-    let group = env::args().nth(3);
+    let user = positional.next();
+    let group = positional.next();
 
     // Create the event loop and TCP listener we'll accept connections on.
     let try_socket = TcpListener::bind(&addr).await;
     let listener = try_socket.expect("Failed to bind");
     println!("Listening on: {}", addr);
 
-    if user != None && group != None {
-        privdrop(&user.unwrap(), &group.unwrap()).expect("Privilege drop failed.");
+    if let (Some(user), Some(group)) = (user, group) {
+        privdrop(&user, &group).expect("Privilege drop failed.");
     } else {
         println!("No user/group privileges to drop to specified.");
     }
     let local = task::LocalSet::new();
     local
         .run_until(async {
+            task::spawn_local(purge_periodically(
+                dao,
+                Duration::from_secs(purge_interval),
+                purge_requests,
+            ));
+
             // Let's spawn the handling of each connection in a separate task.
             while let Ok((stream, addr)) = listener.accept().await {
                 task::spawn_local(handle_connection(dispatcher.clone(), stream, addr));
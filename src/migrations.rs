@@ -0,0 +1,289 @@
+use crate::data_model::{time_point_from_str, time_point_to_nanos};
+use crate::errors::HandlingError;
+use rusqlite::{params, Connection, Error, Transaction};
+
+/// A step from one schema version to the next. The version a migration leads to is its position
+/// in MIGRATIONS plus one, version 0 being the empty database.
+pub struct Migration {
+    pub description: &'static str,
+    pub apply: fn(&Transaction) -> Result<(), HandlingError>,
+}
+
+/// Append only: released migrations must never be changed or reordered.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "Create plots, time series and entries",
+        apply: create_schema,
+    },
+    Migration {
+        description: "Store time points as nanoseconds since the epoch",
+        apply: store_nanos,
+    },
+    Migration {
+        description: "Cluster entries by time series and time point",
+        apply: cluster_entries,
+    },
+    Migration {
+        description: "Add retention to plots and time series",
+        apply: add_retention,
+    },
+];
+
+/// The schema version this binary works with.
+pub fn latest_version() -> i64 {
+    MIGRATIONS.len() as i64
+}
+
+pub fn schema_version(conn: &Connection) -> Result<i64, Error> {
+    conn.query_row("PRAGMA user_version", (), |row| row.get(0))
+}
+
+/// Applies all migrations the database is missing, each in its own transaction together with the
+/// new version number, so that a failing migration leaves the database at the previous version.
+/// Returns the versions before and after.
+pub fn migrate(conn: &mut Connection) -> Result<(i64, i64), HandlingError> {
+    migrate_to(conn, latest_version())
+}
+
+/// Migrates up to the given version only, e.g. to compare the performance of schema versions.
+pub fn migrate_to(conn: &mut Connection, target: i64) -> Result<(i64, i64), HandlingError> {
+    let from = schema_version(conn)?;
+    if from > latest_version() {
+        return Err(HandlingError::Internal(format!(
+            "Database schema version {} is newer than the supported version {}.",
+            from,
+            latest_version()
+        )));
+    }
+
+    for (index, migration) in MIGRATIONS
+        .iter()
+        .enumerate()
+        .take(target as usize)
+        .skip(from as usize)
+    {
+        let version = index as i64 + 1;
+        let tx = conn.transaction()?;
+        (migration.apply)(&tx)?;
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+        println!(
+            "Migrated database to version {}: {}",
+            version, migration.description
+        );
+    }
+    Ok((from, from.max(target.min(latest_version()))))
+}
+
+// Databases from before versioning have all of this already, hence IF NOT EXISTS.
+fn create_schema(tx: &Transaction) -> Result<(), HandlingError> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS plot (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            description TEXT NOT NULL
+        )",
+        (), // empty list of parameters.
+    )?;
+
+    tx.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS index_plot_name
+            ON plot ( name )",
+        (), // empty list of parameters.
+    )?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS time_series (
+            id INTEGER PRIMARY KEY,
+            plot_id INTEGER,
+            name TEXT NOT NULL,
+            unit TEXT NOT NULL,
+            FOREIGN KEY(plot_id) REFERENCES plot(id)
+        )",
+        (), // empty list of parameters.
+    )?;
+    tx.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS index_time_series_name
+            ON time_series ( plot_id, name )",
+        (), // empty list of parameters.
+    )?;
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS time_series_entry (
+            time_series_id INTEGER,
+            date TEXT NOT NULL,
+            value REAL NOT NULL,
+            FOREIGN KEY(time_series_id) REFERENCES time_series(id)
+        )",
+        (), // empty list of parameters.
+    )?;
+
+    tx.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS index_time_series_entry
+            ON time_series_entry ( time_series_id, date)",
+        (), // empty list of parameters.
+    )?;
+
+    Ok(())
+}
+
+// Text compares correctly only while all time points have the same offset and precision. A
+// column's type cannot be changed in place, so the table is rebuilt. Texts that differ only in
+// offset or precision name the same time point; of those the last one written is kept.
+fn store_nanos(tx: &Transaction) -> Result<(), HandlingError> {
+    tx.execute(
+        "CREATE TABLE time_series_entry_nanos (
+            time_series_id INTEGER,
+            date INTEGER NOT NULL,
+            value REAL NOT NULL,
+            FOREIGN KEY(time_series_id) REFERENCES time_series(id)
+        )",
+        (), // empty list of parameters.
+    )?;
+    tx.execute(
+        "CREATE UNIQUE INDEX index_time_series_entry_nanos
+            ON time_series_entry_nanos ( time_series_id, date)",
+        (), // empty list of parameters.
+    )?;
+
+    let mut read = 0;
+    {
+        let mut select =
+            tx.prepare("SELECT time_series_id, date, value FROM time_series_entry ORDER BY rowid")?;
+        let mut insert = tx.prepare(
+            "INSERT OR REPLACE INTO time_series_entry_nanos (time_series_id, date, value)
+                VALUES (?1, ?2, ?3)",
+        )?;
+        let mut rows = select.query(())?;
+        while let Some(row) = rows.next()? {
+            let date: String = row.get(1)?;
+            let nanos = time_point_to_nanos(&time_point_from_str(&date)?).ok_or_else(|| {
+                HandlingError::Parse {
+                    message: "Time points must be between the years 1677 and 2262.".to_string(),
+                    value: date.clone(),
+                }
+            })?;
+            insert.execute(params![
+                row.get::<_, Option<i64>>(0)?,
+                nanos,
+                row.get::<_, f64>(2)?
+            ])?;
+            read += 1;
+        }
+    }
+    let written: i64 = tx.query_row("SELECT COUNT(*) FROM time_series_entry_nanos", (), |row| {
+        row.get(0)
+    })?;
+    if written < read {
+        println!(
+            "Merged {} entries into later ones of the same time series and time point.",
+            read - written
+        );
+    }
+
+    tx.execute("DROP TABLE time_series_entry", ())?;
+    tx.execute(
+        "ALTER TABLE time_series_entry_nanos RENAME TO time_series_entry",
+        (),
+    )?;
+    // Indexes keep their names through a rename, this one takes over the name of the old one.
+    tx.execute("DROP INDEX index_time_series_entry_nanos", ())?;
+    tx.execute(
+        "CREATE UNIQUE INDEX index_time_series_entry
+            ON time_series_entry ( time_series_id, date)",
+        (), // empty list of parameters.
+    )?;
+
+    Ok(())
+}
+
+// Without rowid the table itself is the B-tree over (time series, time point), so range scans read
+// consecutive pages instead of looking up every row from a separate index. Entries without a time
+// series cannot be part of the key; they were unreachable anyway and are dropped.
+fn cluster_entries(tx: &Transaction) -> Result<(), HandlingError> {
+    tx.execute(
+        "CREATE TABLE time_series_entry_clustered (
+            time_series_id INTEGER NOT NULL,
+            date INTEGER NOT NULL,
+            value REAL NOT NULL,
+            PRIMARY KEY (time_series_id, date),
+            FOREIGN KEY(time_series_id) REFERENCES time_series(id)
+        ) WITHOUT ROWID",
+        (), // empty list of parameters.
+    )?;
+    tx.execute(
+        "INSERT INTO time_series_entry_clustered (time_series_id, date, value)
+            SELECT time_series_id, date, value FROM time_series_entry
+            WHERE time_series_id IS NOT NULL ORDER BY time_series_id, date",
+        (), // empty list of parameters.
+    )?;
+
+    // The unique index goes together with the old table.
+    tx.execute("DROP TABLE time_series_entry", ())?;
+    tx.execute(
+        "ALTER TABLE time_series_entry_clustered RENAME TO time_series_entry",
+        (),
+    )?;
+
+    Ok(())
+}
+
+// The retention is in seconds, NULL keeps entries forever. The purge statistics are kept with the
+// time series, so that they survive restarts.
+fn add_retention(tx: &Transaction) -> Result<(), HandlingError> {
+    tx.execute("ALTER TABLE plot ADD COLUMN retention INTEGER", ())?;
+    tx.execute("ALTER TABLE time_series ADD COLUMN retention INTEGER", ())?;
+    tx.execute(
+        "ALTER TABLE time_series ADD COLUMN purged_entries INTEGER NOT NULL DEFAULT 0",
+        (),
+    )?;
+    tx.execute("ALTER TABLE time_series ADD COLUMN last_purge INTEGER", ())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nanos(time_point: &str) -> i64 {
+        time_point_to_nanos(&time_point_from_str(time_point).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn text_time_points_are_converted_to_nanos() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_to(&mut conn, 1).unwrap();
+        conn.execute_batch(
+            "INSERT INTO plot (id, name, description) VALUES (1, 'plot', '');
+            INSERT INTO time_series (id, plot_id, name, unit) VALUES (1, 1, 'series', '');
+            INSERT INTO time_series_entry (time_series_id, date, value) VALUES
+                (1, '2020-01-01T00:30:00+01:00', 1.0),
+                (1, '2020-01-01T00:00:00.250Z', 2.0),
+                (1, '2020-01-01T00:00:00Z', 3.0),
+                (1, '2020-01-01T02:00:00Z', 4.0),
+                (1, '2020-01-01T01:00:00.250+01:00', 5.0);",
+        )
+        .unwrap();
+
+        assert_eq!(migrate(&mut conn).unwrap(), (1, latest_version()));
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+
+        // As text, the first entry would sort into the range; it is half an hour before it. The
+        // last one names the same time point as the second and replaces it.
+        let mut stmt = conn
+            .prepare(
+                "SELECT value FROM time_series_entry
+                WHERE time_series_id = 1 AND date >= (?1) AND date < (?2) ORDER BY date",
+            )
+            .unwrap();
+        let values = stmt
+            .query_map(
+                params![nanos("2020-01-01T00:00:00Z"), nanos("2020-01-01T01:00:00Z")],
+                |row| row.get::<_, f64>(0),
+            )
+            .unwrap()
+            .collect::<Result<Vec<f64>, _>>()
+            .unwrap();
+        assert_eq!(values, vec![3.0, 5.0]);
+    }
+}